@@ -0,0 +1,192 @@
+use actix_session::SessionExt;
+use actix_web::dev::Payload;
+use actix_web::http::{StatusCode, header};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, mime, web};
+use actix_web::{HttpResponseBuilder, ResponseError};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use futures_util::future::LocalBoxFuture;
+use snafu::Location;
+use snafu::prelude::*;
+
+use crate::DbPool;
+use crate::verification::VerificationPolicy;
+
+/// The user a request is made by, from either the session cookie or an
+/// `Authorization: Bearer` API token. Taking it as an argument makes a route require login.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticatedUser {
+    pub user_id: i32,
+}
+
+/// A signed in user that the [`VerificationPolicy`] still lets sync. Taking it as an argument
+/// makes a route a sync route.
+#[derive(Debug, Clone, Copy)]
+pub struct SyncingUser {
+    pub user_id: i32,
+}
+
+#[derive(Debug, Snafu)]
+pub enum AuthError {
+    #[snafu(display("Not logged in"))]
+    Unauthorized,
+    #[snafu(display("Invalid API token"))]
+    InvalidToken,
+    #[snafu(display("Verify your email address to keep syncing"))]
+    Unverified,
+    #[snafu(display("Internal server error. Please try again later."))]
+    Pool {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel_async::pooled_connection::deadpool::PoolError,
+    },
+    #[snafu(display("Internal server error. Please try again later."))]
+    Query {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthorized | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            AuthError::Unverified => StatusCode::FORBIDDEN,
+            AuthError::Pool { .. } | AuthError::Query { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response_builder.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+        }
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let request = request.clone();
+        Box::pin(async move { authenticate(&request).await })
+    }
+}
+
+impl FromRequest for SyncingUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let request = request.clone();
+        Box::pin(async move {
+            let user = authenticate(&request).await?;
+            ensure_may_sync(&request, user.user_id).await?;
+
+            Ok(SyncingUser {
+                user_id: user.user_id,
+            })
+        })
+    }
+}
+
+/// Checks the user against the [`VerificationPolicy`], for sync routes that don't resolve the
+/// user through [`SyncingUser`].
+pub(crate) async fn ensure_may_sync(request: &HttpRequest, user_id: i32) -> Result<(), AuthError> {
+    use app_db::schema::users;
+
+    let policy = request
+        .app_data::<web::Data<VerificationPolicy>>()
+        .expect("verification policy is registered as app data");
+    let db_pool = request
+        .app_data::<web::Data<DbPool>>()
+        .expect("database pool is registered as app data");
+    let mut conn = db_pool.get().await.context(PoolSnafu)?;
+
+    // the account can be gone while a session or ticket for it is still around
+    let (created_at, verified_at) = users::table
+        .find(user_id)
+        .select((users::created_at, users::verified_at))
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .context(QuerySnafu)?
+        .context(UnauthorizedSnafu)?;
+    ensure!(
+        policy.may_sync(created_at, verified_at, Utc::now()),
+        UnverifiedSnafu
+    );
+
+    Ok(())
+}
+
+/// The user's session generation, which `/logout/all` and password resets move on to revoke
+/// the websocket tickets issued before them.
+pub(crate) async fn session_generation(
+    request: &HttpRequest,
+    user_id: i32,
+) -> Result<i32, AuthError> {
+    use app_db::schema::users;
+
+    let db_pool = request
+        .app_data::<web::Data<DbPool>>()
+        .expect("database pool is registered as app data");
+    let mut conn = db_pool.get().await.context(PoolSnafu)?;
+
+    users::table
+        .find(user_id)
+        .select(users::session_generation)
+        .get_result(&mut conn)
+        .await
+        .optional()
+        .context(QuerySnafu)?
+        .context(UnauthorizedSnafu)
+}
+
+/// A bearer token wins over the cookie, so a tool that sends both acts as the token's user.
+async fn authenticate(request: &HttpRequest) -> Result<AuthenticatedUser, AuthError> {
+    if let Some(token) = bearer_token(request) {
+        let db_pool = request
+            .app_data::<web::Data<DbPool>>()
+            .expect("database pool is registered as app data");
+        let mut conn = db_pool.get().await.context(PoolSnafu)?;
+        let user_id = find_token_user(&mut conn, token)
+            .await
+            .context(QuerySnafu)?
+            .context(InvalidTokenSnafu)?;
+
+        return Ok(AuthenticatedUser { user_id });
+    }
+
+    match request.get_session().get::<i32>("user_id") {
+        Ok(Some(user_id)) => Ok(AuthenticatedUser { user_id }),
+        _ => UnauthorizedSnafu.fail(),
+    }
+}
+
+fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    let authorization = request.headers().get(header::AUTHORIZATION)?;
+    authorization.to_str().ok()?.strip_prefix("Bearer ")
+}
+
+/// Looks up the user a token was issued to, noting that the token was used.
+async fn find_token_user(
+    conn: &mut AsyncPgConnection,
+    token: &str,
+) -> Result<Option<i32>, diesel::result::Error> {
+    use app_db::schema::api_tokens::dsl::*;
+
+    diesel::update(api_tokens.filter(token_hash.eq(auth_utils::hash_token(token))))
+        .set(last_used_at.eq(Utc::now()))
+        .returning(user_id)
+        .get_result(conn)
+        .await
+        .optional()
+}
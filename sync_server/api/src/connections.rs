@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_lock::Mutex;
+
+pub type ConnectionId = u64;
+
+/// Open websocket connections grouped by the user they belong to, so an event applied for
+/// one device can be forwarded to the user's other devices.
+pub struct ConnectionRegistry<T> {
+    connections: Arc<Mutex<HashMap<i32, HashMap<ConnectionId, T>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+// derive(Clone) would require T: Clone for the handle itself
+impl<T> Clone for ConnectionRegistry<T> {
+    fn clone(&self) -> Self {
+        Self {
+            connections: self.connections.clone(),
+            next_id: self.next_id.clone(),
+        }
+    }
+}
+
+impl<T> Default for ConnectionRegistry<T> {
+    fn default() -> Self {
+        Self {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl<T: Clone> ConnectionRegistry<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn register(&self, user_id: i32, connection: T) -> ConnectionId {
+        let connection_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut connections = self.connections.lock().await;
+        connections
+            .entry(user_id)
+            .or_default()
+            .insert(connection_id, connection);
+
+        connection_id
+    }
+
+    pub async fn unregister(&self, user_id: i32, connection_id: ConnectionId) {
+        let mut connections = self.connections.lock().await;
+        if let Some(user_connections) = connections.get_mut(&user_id) {
+            user_connections.remove(&connection_id);
+            if user_connections.is_empty() {
+                connections.remove(&user_id);
+            }
+        }
+    }
+
+    /// Removes every connection the user has open, returning them so they can be closed.
+    pub async fn remove_user(&self, user_id: i32) -> Vec<T> {
+        let mut connections = self.connections.lock().await;
+        connections
+            .remove(&user_id)
+            .map(|user_connections| user_connections.into_values().collect())
+            .unwrap_or_default()
+    }
+
+    /// The user's other open connections, never including `sender`.
+    pub async fn peers(&self, user_id: i32, sender: ConnectionId) -> Vec<(ConnectionId, T)> {
+        let connections = self.connections.lock().await;
+        let Some(user_connections) = connections.get(&user_id) else {
+            return Vec::new();
+        };
+
+        user_connections
+            .iter()
+            .filter(|(connection_id, _)| **connection_id != sender)
+            .map(|(connection_id, connection)| (*connection_id, connection.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn peers_exclude_sender() {
+        let registry = ConnectionRegistry::new();
+        let ipad = registry.register(1, "ipad").await;
+        let iphone = registry.register(1, "iphone").await;
+        registry.register(2, "other user").await;
+
+        let peers = registry.peers(1, ipad).await;
+        assert_eq!(peers, vec![(iphone, "iphone")]);
+    }
+
+    #[actix_web::test]
+    async fn unregister_removes_connection() {
+        let registry = ConnectionRegistry::new();
+        let ipad = registry.register(1, "ipad").await;
+        let iphone = registry.register(1, "iphone").await;
+
+        registry.unregister(1, iphone).await;
+
+        assert!(registry.peers(1, ipad).await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn remove_user_returns_their_connections() {
+        let registry = ConnectionRegistry::new();
+        let ipad = registry.register(1, "ipad").await;
+        registry.register(1, "iphone").await;
+        let other = registry.register(2, "other user").await;
+
+        let mut removed = registry.remove_user(1).await;
+        removed.sort();
+        assert_eq!(removed, vec!["ipad", "iphone"]);
+        assert!(registry.peers(1, ipad).await.is_empty());
+        assert_eq!(
+            registry.peers(2, other + 1).await,
+            vec![(other, "other user")]
+        );
+    }
+}
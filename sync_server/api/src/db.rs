@@ -1,16 +1,57 @@
 use crate::Email;
+use crate::NewApiToken;
+use crate::NewEmailVerification;
+use crate::NewPasswordReset;
 use crate::User;
 use crate::UserInput;
+use chrono::{DateTime, Utc};
 use diesel::ExpressionMethods;
+use diesel::OptionalExtension;
 use diesel::QueryDsl;
 use diesel::SelectableHelper;
 use diesel::result::Error;
-use diesel_async::RunQueryDsl;
 use diesel_async::pg;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, RunQueryDsl};
 
 pub trait Database {
     fn get_user(&mut self, user_email: &Email) -> impl Future<Output = Result<User, Error>>;
-    fn create_user(&mut self, user: UserInput) -> impl Future<Output = Result<(), Error>>;
+    fn get_user_by_id(&mut self, user_id: i32) -> impl Future<Output = Result<User, Error>>;
+    /// Returns the new user's id.
+    fn create_user(&mut self, user: UserInput) -> impl Future<Output = Result<i32, Error>>;
+    fn create_api_token(
+        &mut self,
+        api_token: NewApiToken,
+    ) -> impl Future<Output = Result<(), Error>>;
+    fn delete_api_tokens(&mut self, user_id: i32) -> impl Future<Output = Result<usize, Error>>;
+    /// Moves the user's session generation on, revoking every websocket ticket issued so far.
+    fn revoke_connection_tickets(
+        &mut self,
+        user_id: i32,
+    ) -> impl Future<Output = Result<(), Error>>;
+    fn create_password_reset(
+        &mut self,
+        reset: NewPasswordReset,
+    ) -> impl Future<Output = Result<(), Error>>;
+    /// Redeems an unused, unexpired reset token, setting the user's new password and revoking
+    /// their API tokens and websocket tickets. Returns the user, or `None` if the token can't be used.
+    fn reset_password(
+        &mut self,
+        token_hash: &str,
+        password_hash: String,
+        now: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Option<i32>, Error>>;
+    fn create_email_verification(
+        &mut self,
+        verification: NewEmailVerification,
+    ) -> impl Future<Output = Result<(), Error>>;
+    /// Redeems an unused, unexpired verification token, marking the user's email address as
+    /// verified. Returns the user, or `None` if the token can't be used.
+    fn verify_email(
+        &mut self,
+        token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Option<i32>, Error>>;
     // fn update_user(&self, id: &str, email: &str, password: &str) -> Result<User, Error>;
     // fn delete_user(&self, id: &str) -> Result<(), Error>;
 }
@@ -38,7 +79,17 @@ impl<'a> Database for DB<'a> {
         Ok(user_id)
     }
 
-    async fn create_user(&mut self, user: UserInput) -> Result<(), Error> {
+    async fn get_user_by_id(&mut self, user_id: i32) -> Result<User, Error> {
+        use app_db::schema::users::dsl::*;
+
+        users
+            .find(user_id)
+            .select(User::as_select())
+            .get_result(&mut self.conn)
+            .await
+    }
+
+    async fn create_user(&mut self, user: UserInput) -> Result<i32, Error> {
         use app_db::schema::users::dsl::*;
 
         // return Err(diesel::result::Error::DatabaseError(
@@ -46,8 +97,18 @@ impl<'a> Database for DB<'a> {
         //     Box::new("Database connection closed".to_string()),
         // ));
 
-        let count = diesel::insert_into(users)
+        diesel::insert_into(users)
             .values(user)
+            .returning(id)
+            .get_result(&mut self.conn)
+            .await
+    }
+
+    async fn create_api_token(&mut self, api_token: NewApiToken) -> Result<(), Error> {
+        use app_db::schema::api_tokens::dsl::*;
+
+        let count = diesel::insert_into(api_tokens)
+            .values(api_token)
             .execute(&mut self.conn)
             .await?;
 
@@ -55,4 +116,150 @@ impl<'a> Database for DB<'a> {
 
         Ok(())
     }
+
+    async fn delete_api_tokens(&mut self, user: i32) -> Result<usize, Error> {
+        use app_db::schema::api_tokens::dsl::*;
+
+        diesel::delete(api_tokens.filter(user_id.eq(user)))
+            .execute(&mut self.conn)
+            .await
+    }
+
+    async fn revoke_connection_tickets(&mut self, user_id: i32) -> Result<(), Error> {
+        use app_db::schema::users;
+
+        diesel::update(users::table.find(user_id))
+            .set(users::session_generation.eq(users::session_generation + 1))
+            .execute(&mut self.conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_password_reset(&mut self, reset: NewPasswordReset) -> Result<(), Error> {
+        use app_db::schema::password_resets::dsl::*;
+
+        let count = diesel::insert_into(password_resets)
+            .values(reset)
+            .execute(&mut self.conn)
+            .await?;
+
+        debug_assert!(count == 1);
+
+        Ok(())
+    }
+
+    async fn reset_password(
+        &mut self,
+        reset_token_hash: &str,
+        new_password_hash: String,
+        now: DateTime<Utc>,
+    ) -> Result<Option<i32>, Error> {
+        use app_db::schema::{api_tokens, password_resets, users};
+
+        self.conn
+            .transaction(|conn| {
+                async move {
+                    // marking the token used in the same statement that checks it keeps two
+                    // requests from both redeeming it
+                    let reset_user_id: Option<i32> = diesel::update(
+                        password_resets::table
+                            .filter(password_resets::token_hash.eq(reset_token_hash))
+                            .filter(password_resets::used_at.is_null())
+                            .filter(password_resets::expires_at.gt(now)),
+                    )
+                    .set(password_resets::used_at.eq(now))
+                    .returning(password_resets::user_id)
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                    let Some(reset_user_id) = reset_user_id else {
+                        return Ok(None);
+                    };
+
+                    diesel::update(users::table.find(reset_user_id))
+                        .set((
+                            users::password_hash.eq(new_password_hash),
+                            users::updated_at.eq(now),
+                            users::session_generation.eq(users::session_generation + 1),
+                        ))
+                        .execute(conn)
+                        .await?;
+                    // any other reset emails still in flight are for the old password
+                    diesel::update(
+                        password_resets::table
+                            .filter(password_resets::user_id.eq(reset_user_id))
+                            .filter(password_resets::used_at.is_null()),
+                    )
+                    .set(password_resets::used_at.eq(now))
+                    .execute(conn)
+                    .await?;
+                    diesel::delete(api_tokens::table.filter(api_tokens::user_id.eq(reset_user_id)))
+                        .execute(conn)
+                        .await?;
+
+                    Ok(Some(reset_user_id))
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn create_email_verification(
+        &mut self,
+        verification: NewEmailVerification,
+    ) -> Result<(), Error> {
+        use app_db::schema::email_verifications::dsl::*;
+
+        let count = diesel::insert_into(email_verifications)
+            .values(verification)
+            .execute(&mut self.conn)
+            .await?;
+
+        debug_assert!(count == 1);
+
+        Ok(())
+    }
+
+    async fn verify_email(
+        &mut self,
+        verification_token_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<i32>, Error> {
+        use app_db::schema::{email_verifications, users};
+
+        self.conn
+            .transaction(|conn| {
+                async move {
+                    let verified_user_id: Option<i32> = diesel::update(
+                        email_verifications::table
+                            .filter(email_verifications::token_hash.eq(verification_token_hash))
+                            .filter(email_verifications::used_at.is_null())
+                            .filter(email_verifications::expires_at.gt(now)),
+                    )
+                    .set(email_verifications::used_at.eq(now))
+                    .returning(email_verifications::user_id)
+                    .get_result(conn)
+                    .await
+                    .optional()?;
+                    let Some(verified_user_id) = verified_user_id else {
+                        return Ok(None);
+                    };
+
+                    // keeps the original time if an older email is confirmed after a newer one
+                    diesel::update(
+                        users::table
+                            .find(verified_user_id)
+                            .filter(users::verified_at.is_null()),
+                    )
+                    .set(users::verified_at.eq(now))
+                    .execute(conn)
+                    .await?;
+
+                    Ok(Some(verified_user_id))
+                }
+                .scope_boxed()
+            })
+            .await
+    }
 }
@@ -1,34 +1,1508 @@
 use bytestring::ByteString;
-use event_database::{CreateProjectEventData, EventDb};
+use event_database::{
+    AddSectionItemNoteEventData, CreateProjectEventData, CreateSectionEventData,
+    CreateSectionItemEventData, DeleteProjectEventData, DeleteSectionEventData,
+    DeleteSectionItemEventData, EditSectionItemNoteEventData, EventDb, EventDbError, EventRecord,
+    Hlc, RemoveSectionItemNoteEventData, RenameSectionEventData, ReorderSectionItemsEventData,
+    ServerClock, SetProjectCompletedEventData, SetSectionItemCompletedEventData,
+    UpdateProjectTitleEventData, UpdateSectionItemTextEventData,
+};
 use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
 
+/// Version of the app database schema that the current events are written against.
+pub const SCHEMA_VERSION: i32 = 1;
+
+/// Oldest app database schema version the server still accepts events from.
+pub const MIN_SUPPORTED_SCHEMA_VERSION: i32 = 1;
+
+/// How far ahead of the server's clock an event's wall time may be. A device with its clock
+/// set far ahead would otherwise win every later edit to the fields it touches.
+pub const MAX_CLOCK_DRIFT: chrono::Duration = chrono::Duration::minutes(5);
+
+/// The envelope a client sends for each event. `id` is generated by the client so a retried
+/// event can be recognized and acknowledged again instead of being applied twice,
+/// `schema_version` is the version of the client's app database, and `hlc` is when the edit
+/// was made, which decides between concurrent edits to the same field.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
-    id: String,
+    id: Uuid,
+    schema_version: i32,
+    hlc: Hlc,
     data: EventData,
 }
 
+impl Event {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn schema_version(&self) -> i32 {
+        self.schema_version
+    }
+}
+
+/// An event envelope before its data is upcast to the current schema version.
+#[derive(Debug, Deserialize)]
+struct RawEvent {
+    id: Uuid,
+    schema_version: i32,
+    /// Missing from clients that predate hybrid logical clocks.
+    hlc: Option<Hlc>,
+    data: serde_json::Value,
+}
+
+/// Converts an event payload written against `from_version` to the next schema version.
+pub type UpcastFn = fn(serde_json::Value) -> Result<serde_json::Value, serde_json::Error>;
+
+/// Steps that bring event payloads from older schema versions up to [`SCHEMA_VERSION`],
+/// keyed by the event type and the version the step upgrades from.
+///
+/// When a schema change alters an event's shape, register a step for that event type from
+/// the previous version instead of rejecting events from clients that haven't updated.
+/// Events are decoded against one registry, built at startup.
+pub struct Upcasters {
+    min_supported_version: i32,
+    current_version: i32,
+    steps: HashMap<(String, i32), UpcastFn>,
+}
+
+impl Default for Upcasters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Upcasters {
+    /// A registry for the schema versions this server supports.
+    pub fn new() -> Self {
+        Self {
+            min_supported_version: MIN_SUPPORTED_SCHEMA_VERSION,
+            current_version: SCHEMA_VERSION,
+            steps: HashMap::new(),
+        }
+    }
+
+    /// A registry for a range of versions other than the server's own, so steps can be tested
+    /// before a schema change needs them.
+    #[cfg(test)]
+    fn for_versions(min_supported_version: i32, current_version: i32) -> Self {
+        Self {
+            min_supported_version,
+            current_version,
+            steps: HashMap::new(),
+        }
+    }
+
+    pub fn is_supported(&self, schema_version: i32) -> bool {
+        (self.min_supported_version..=self.current_version).contains(&schema_version)
+    }
+
+    pub fn register(mut self, event_type: &str, from_version: i32, step: UpcastFn) -> Self {
+        self.steps
+            .insert((event_type.to_string(), from_version), step);
+        self
+    }
+
+    /// Applies each registered step in version order, then parses the current event shape.
+    /// Versions without a step for the event's type leave the payload unchanged.
+    pub fn upcast(
+        &self,
+        schema_version: i32,
+        mut data: serde_json::Value,
+    ) -> Result<EventData, serde_json::Error> {
+        for version in schema_version..self.current_version {
+            let event_type = data["type"].as_str().unwrap_or_default().to_string();
+            if let Some(step) = self.steps.get(&(event_type, version)) {
+                data = step(data)?;
+            }
+        }
+
+        serde_json::from_value(data)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum EventData {
     CreateProject(CreateProjectEventData),
+    UpdateProjectTitle(UpdateProjectTitleEventData),
+    SetProjectCompleted(SetProjectCompletedEventData),
+    DeleteProject(DeleteProjectEventData),
+    CreateSection(CreateSectionEventData),
+    RenameSection(RenameSectionEventData),
+    DeleteSection(DeleteSectionEventData),
+    CreateSectionItem(CreateSectionItemEventData),
+    UpdateSectionItemText(UpdateSectionItemTextEventData),
+    SetSectionItemCompleted(SetSectionItemCompletedEventData),
+    DeleteSectionItem(DeleteSectionItemEventData),
+    ReorderSectionItems(ReorderSectionItemsEventData),
+    AddSectionItemNote(AddSectionItemNoteEventData),
+    EditSectionItemNote(EditSectionItemNoteEventData),
+    RemoveSectionItemNote(RemoveSectionItemNoteEventData),
+}
+
+/// Frames the server sends to a connected client.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// An event applied from one of the user's other devices.
+    Event { sequence: i64, event: Event },
+    /// The event is in the event log and can be dropped from the client's outbox. When
+    /// `superseded` is set it didn't change anything, since a later edit or delete of the row
+    /// had already been applied, so the client should sync the row to reconcile.
+    Ack {
+        event_id: Uuid,
+        sequence: i64,
+        superseded: bool,
+    },
+    /// The event was not applied. `event_id` is missing when the frame couldn't be parsed.
+    Nack {
+        event_id: Option<Uuid>,
+        code: RejectionCode,
+        reason: String,
+    },
+    /// Every event in the batch was applied, superseded, or had been already, in the order they
+    /// were sent.
+    BatchAck { batch_id: Uuid, acks: Vec<EventAck> },
+    /// None of the batch was applied. `event_id` is the event that was rejected, if one was.
+    BatchNack {
+        batch_id: Uuid,
+        event_id: Option<Uuid>,
+        code: RejectionCode,
+        reason: String,
+    },
+    /// The frame was dropped without being applied because the connection is sending faster
+    /// than it's allowed to. The client should resend it after `retry_after_ms`.
+    SlowDown { retry_after_ms: u64 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EventAck {
+    pub event_id: Uuid,
+    pub sequence: i64,
+    /// Same as for [`ServerMessage::Ack`].
+    pub superseded: bool,
+}
+
+/// Machine readable reason an event was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionCode {
+    InvalidEvent,
+    UnsupportedSchemaVersion,
+    NotFound,
+    AlreadyExists,
+    ReorderMismatch,
+    /// The event points at a row that belongs to a different user than the one the connection
+    /// is signed in as.
+    WrongUser,
+    /// A later edit to the same field won or the row was deleted, the client should sync the
+    /// row to reconcile.
+    Superseded,
+    /// The event's clock is too far ahead of the server's. The client should correct its
+    /// clock and stamp the event again.
+    ClockAhead,
+    InternalError,
+}
+
+impl From<&EventDbError> for RejectionCode {
+    fn from(err: &EventDbError) -> Self {
+        use diesel::result::{DatabaseErrorKind, Error};
+
+        match err {
+            EventDbError::NotFound { .. } => RejectionCode::NotFound,
+            EventDbError::ReorderMismatch { .. } => RejectionCode::ReorderMismatch,
+            EventDbError::Superseded { .. } => RejectionCode::Superseded,
+            EventDbError::NotOwned { .. } => RejectionCode::WrongUser,
+            EventDbError::Database { source, .. } => match source {
+                Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                    RejectionCode::NotFound
+                }
+                Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    RejectionCode::AlreadyExists
+                }
+                _ => RejectionCode::InternalError,
+            },
+        }
+    }
 }
 
-pub fn deserialize_event(event: ByteString) -> Result<Event, serde_json::Error> {
-    let data = serde_json::from_slice(event.as_bytes())?;
+impl HandleEventError<EventDbError> {
+    /// The rejection to send back for an event that couldn't be applied.
+    pub fn nack(&self, event_id: Uuid) -> ServerMessage {
+        ServerMessage::Nack {
+            event_id: Some(event_id),
+            code: RejectionCode::from(self),
+            reason: self.to_string(),
+        }
+    }
+}
+
+impl From<&HandleEventError<EventDbError>> for RejectionCode {
+    fn from(err: &HandleEventError<EventDbError>) -> Self {
+        match err {
+            HandleEventError::Database { source } => RejectionCode::from(source),
+            HandleEventError::Rollback { .. } => RejectionCode::InternalError,
+        }
+    }
+}
+
+/// How a socket's frames are encoded, chosen by the client when it connects. JSON stays the
+/// default since it's readable while debugging, CBOR is smaller for large offline batches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+}
+
+impl ServerMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("server message serializes to json")
+    }
+
+    /// Encodes the message the way [`ServerMessage::to_json`] does, ids included as text, so
+    /// clients see one envelope in either encoding.
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let message = serde_json::to_value(self).expect("server message serializes to json");
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&message, &mut bytes).expect("writing to a vec can't fail");
+        bytes
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum DeserializeEventError {
+    #[snafu(display("Invalid event: {source}"))]
+    InvalidEvent {
+        event_id: Option<Uuid>,
+        source: serde_json::Error,
+    },
+    #[snafu(display(
+        "Schema version {schema_version} is not supported, supported versions are {min_supported_version} to {current_version}"
+    ))]
+    UnsupportedSchemaVersion {
+        event_id: Uuid,
+        schema_version: i32,
+        min_supported_version: i32,
+        current_version: i32,
+    },
+    #[snafu(display(
+        "Clock node is {length} bytes long, at most {} are allowed",
+        Hlc::MAX_NODE_LENGTH
+    ))]
+    NodeTooLong { event_id: Uuid, length: usize },
+    #[snafu(display(
+        "Clock is ahead of the server's, which is at {server_wall_time} ms since the epoch"
+    ))]
+    ClockAhead {
+        event_id: Uuid,
+        server_wall_time: u64,
+    },
+}
+
+impl DeserializeEventError {
+    pub fn event_id(&self) -> Option<Uuid> {
+        match self {
+            DeserializeEventError::InvalidEvent { event_id, .. } => *event_id,
+            DeserializeEventError::UnsupportedSchemaVersion { event_id, .. }
+            | DeserializeEventError::NodeTooLong { event_id, .. }
+            | DeserializeEventError::ClockAhead { event_id, .. } => Some(*event_id),
+        }
+    }
+
+    pub fn code(&self) -> RejectionCode {
+        match self {
+            DeserializeEventError::InvalidEvent { .. }
+            | DeserializeEventError::NodeTooLong { .. } => RejectionCode::InvalidEvent,
+            DeserializeEventError::UnsupportedSchemaVersion { .. } => {
+                RejectionCode::UnsupportedSchemaVersion
+            }
+            DeserializeEventError::ClockAhead { .. } => RejectionCode::ClockAhead,
+        }
+    }
+}
+
+/// Parses an event envelope and upcasts its data from the client's schema version. Events
+/// without a clock are stamped with the server's, which every client clock is merged into.
+fn decode_event(
+    event: serde_json::Value,
+    upcasters: &Upcasters,
+    clock: &ServerClock,
+) -> Result<Event, DeserializeEventError> {
+    let raw: RawEvent =
+        serde_json::from_value(event).context(InvalidEventSnafu { event_id: None })?;
+
+    ensure!(
+        upcasters.is_supported(raw.schema_version),
+        UnsupportedSchemaVersionSnafu {
+            event_id: raw.id,
+            schema_version: raw.schema_version,
+            min_supported_version: upcasters.min_supported_version,
+            current_version: upcasters.current_version,
+        }
+    );
+
+    let data = upcasters
+        .upcast(raw.schema_version, raw.data)
+        .context(InvalidEventSnafu {
+            event_id: Some(raw.id),
+        })?;
+
+    let hlc = match raw.hlc {
+        Some(hlc) => {
+            check_clock(raw.id, &hlc)?;
+            clock.observe(&hlc);
+            hlc
+        }
+        None => clock.now(),
+    };
+
     Ok(Event {
-        id: uuid::Uuid::new_v4().to_string(),
+        id: raw.id,
+        schema_version: raw.schema_version,
+        hlc,
         data,
     })
 }
 
-pub async fn handle_event<T>(user_id: i32, event: Event, db: &mut T) -> Result<(), T::Error>
+fn check_clock(event_id: Uuid, hlc: &Hlc) -> Result<(), DeserializeEventError> {
+    ensure!(
+        hlc.node().len() <= Hlc::MAX_NODE_LENGTH,
+        NodeTooLongSnafu {
+            event_id,
+            length: hlc.node().len(),
+        }
+    );
+
+    let server_wall_time = ServerClock::wall_time();
+    let max_drift = MAX_CLOCK_DRIFT.num_milliseconds() as u64;
+    ensure!(
+        hlc.wall_time() <= server_wall_time.saturating_add(max_drift),
+        ClockAheadSnafu {
+            event_id,
+            server_wall_time,
+        }
+    );
+
+    Ok(())
+}
+
+/// Most events a client can send in one batch.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// A frame a client sends: one event, or a batch of queued events applied all-or-nothing.
+#[derive(Debug, Clone)]
+pub enum ClientMessage {
+    Event(Event),
+    Batch { batch_id: Uuid, events: Vec<Event> },
+}
+
+/// A batch envelope before its events are parsed.
+#[derive(Debug, Deserialize)]
+struct RawBatch {
+    batch_id: Uuid,
+    events: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum DeserializeMessageError {
+    #[snafu(display("Invalid message: {source}"))]
+    InvalidMessage { source: serde_json::Error },
+    #[snafu(display("Invalid CBOR message: {source}"))]
+    InvalidCbor {
+        source: ciborium::de::Error<std::io::Error>,
+    },
+    #[snafu(transparent)]
+    Event { source: DeserializeEventError },
+    #[snafu(display("{source}"))]
+    BatchEvent {
+        batch_id: Uuid,
+        source: DeserializeEventError,
+    },
+    #[snafu(display("Batch has {size} events, at most {MAX_BATCH_SIZE} are allowed"))]
+    BatchTooLarge { batch_id: Uuid, size: usize },
+}
+
+impl DeserializeMessageError {
+    /// The rejection to send back for a frame that couldn't be parsed.
+    pub fn nack(&self) -> ServerMessage {
+        let reason = self.to_string();
+        match self {
+            DeserializeMessageError::InvalidMessage { .. }
+            | DeserializeMessageError::InvalidCbor { .. } => ServerMessage::Nack {
+                event_id: None,
+                code: RejectionCode::InvalidEvent,
+                reason,
+            },
+            DeserializeMessageError::Event { source } => ServerMessage::Nack {
+                event_id: source.event_id(),
+                code: source.code(),
+                reason,
+            },
+            DeserializeMessageError::BatchEvent { batch_id, source } => ServerMessage::BatchNack {
+                batch_id: *batch_id,
+                event_id: source.event_id(),
+                code: source.code(),
+                reason,
+            },
+            DeserializeMessageError::BatchTooLarge { batch_id, .. } => ServerMessage::BatchNack {
+                batch_id: *batch_id,
+                event_id: None,
+                code: RejectionCode::InvalidEvent,
+                reason,
+            },
+        }
+    }
+}
+
+/// Parses a JSON text frame from a client.
+pub fn deserialize_message(
+    message: ByteString,
+    upcasters: &Upcasters,
+    clock: &ServerClock,
+) -> Result<ClientMessage, DeserializeMessageError> {
+    let message = serde_json::from_slice(message.as_bytes()).context(InvalidMessageSnafu)?;
+
+    decode_message(message, upcasters, clock)
+}
+
+/// Parses a CBOR binary frame from a client. The envelope is the same as the JSON one, with
+/// ids as text, so both decode into the same structure before the events are parsed.
+pub fn deserialize_cbor_message(
+    message: &[u8],
+    upcasters: &Upcasters,
+    clock: &ServerClock,
+) -> Result<ClientMessage, DeserializeMessageError> {
+    let message = ciborium::from_reader(message).context(InvalidCborSnafu)?;
+
+    decode_message(message, upcasters, clock)
+}
+
+/// Parses a decoded frame. Batches are told apart from single events by their `events` list.
+fn decode_message(
+    message: serde_json::Value,
+    upcasters: &Upcasters,
+    clock: &ServerClock,
+) -> Result<ClientMessage, DeserializeMessageError> {
+    if message.get("events").is_none() {
+        return Ok(ClientMessage::Event(decode_event(
+            message, upcasters, clock,
+        )?));
+    }
+
+    let batch: RawBatch = serde_json::from_value(message).context(InvalidMessageSnafu)?;
+    ensure!(
+        batch.events.len() <= MAX_BATCH_SIZE,
+        BatchTooLargeSnafu {
+            batch_id: batch.batch_id,
+            size: batch.events.len(),
+        }
+    );
+
+    let events = batch
+        .events
+        .into_iter()
+        .map(|event| decode_event(event, upcasters, clock))
+        .collect::<Result<_, _>>()
+        .context(BatchEventSnafu {
+            batch_id: batch.batch_id,
+        })?;
+
+    Ok(ClientMessage::Batch {
+        batch_id: batch.batch_id,
+        events,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventOutcome {
+    /// The event was applied and appended to the event log.
+    Applied { sequence: i64 },
+    /// The event was already in the event log, so it wasn't applied again.
+    Replayed { sequence: i64 },
+    /// The event was appended to the event log, but a later edit or delete of the same row had
+    /// already been applied so it changed nothing.
+    Superseded { sequence: i64 },
+}
+
+#[derive(Debug, Snafu)]
+pub enum HandleEventError<E: std::error::Error + 'static> {
+    #[snafu(transparent)]
+    Database { source: E },
+    /// The batch failed and so did rolling it back. `original` is why the batch failed.
+    #[snafu(display("Rolling back after \"{original}\" failed: {source}"))]
+    Rollback {
+        source: E,
+        original: Box<HandleEventError<E>>,
+    },
+}
+
+/// Applies the event and appends it to the event log in one transaction. An event the user
+/// already sent is answered with its original sequence number instead of being reapplied.
+pub async fn handle_event<T>(
+    user_id: i32,
+    event: Event,
+    db: &mut T,
+) -> Result<EventOutcome, HandleEventError<T::Error>>
+where
+    T: EventDb<Error = EventDbError>,
+{
+    let mut outcomes = handle_batch(user_id, vec![event], db)
+        .await
+        .map_err(|err| err.source)?;
+
+    Ok(outcomes.pop().expect("one outcome per event"))
+}
+
+/// Why a batch was rolled back. `event_id` is the event that failed, or missing when the
+/// transaction itself failed.
+#[derive(Debug)]
+pub struct BatchError<E: std::error::Error + 'static> {
+    pub event_id: Option<Uuid>,
+    pub source: HandleEventError<E>,
+}
+
+impl BatchError<EventDbError> {
+    /// The rejection to send back for a batch that was rolled back.
+    pub fn nack(&self, batch_id: Uuid) -> ServerMessage {
+        ServerMessage::BatchNack {
+            batch_id,
+            event_id: self.event_id,
+            code: RejectionCode::from(&self.source),
+            reason: self.source.to_string(),
+        }
+    }
+}
+
+/// Applies the events in order in one transaction, so either all of them are applied or none
+/// are. An event that lost to a later edit doesn't fail the batch, it's only reported as
+/// superseded. Outcomes are returned in the same order as the events. `user_id` is who the
+/// connection is authenticated as, and the events only ever change their rows.
+pub async fn handle_batch<T>(
+    user_id: i32,
+    events: Vec<Event>,
+    db: &mut T,
+) -> Result<Vec<EventOutcome>, BatchError<T::Error>>
 where
-    T: EventDb,
-    T::Error: std::error::Error,
+    T: EventDb<Error = EventDbError>,
 {
-    dbg!(&event);
-    match event.data {
-        EventData::CreateProject(data) => db.handle_create_project(data).await,
+    let transaction_error = |source| BatchError {
+        event_id: None,
+        source: HandleEventError::Database { source },
+    };
+
+    db.begin_transaction().await.map_err(transaction_error)?;
+
+    match apply_batch(user_id, events, db).await {
+        Ok(outcomes) => {
+            db.commit_transaction().await.map_err(transaction_error)?;
+            Ok(outcomes)
+        }
+        Err(err) => match db.rollback_transaction().await {
+            Ok(()) => Err(err),
+            Err(source) => Err(BatchError {
+                event_id: err.event_id,
+                source: HandleEventError::Rollback {
+                    source,
+                    original: Box::new(err.source),
+                },
+            }),
+        },
+    }
+}
+
+async fn apply_batch<T>(
+    user_id: i32,
+    events: Vec<Event>,
+    db: &mut T,
+) -> Result<Vec<EventOutcome>, BatchError<T::Error>>
+where
+    T: EventDb<Error = EventDbError>,
+{
+    db.lock_user_changes(user_id)
+        .await
+        .map_err(|source| BatchError {
+            event_id: None,
+            source: HandleEventError::Database { source },
+        })?;
+
+    let mut outcomes = Vec::with_capacity(events.len());
+    for event in events {
+        let event_id = event.id;
+        let outcome = apply_or_replay(user_id, event, db)
+            .await
+            .map_err(|source| BatchError {
+                event_id: Some(event_id),
+                source: HandleEventError::Database { source },
+            })?;
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+async fn apply_or_replay<T>(
+    user_id: i32,
+    event: Event,
+    db: &mut T,
+) -> Result<EventOutcome, T::Error>
+where
+    T: EventDb<Error = EventDbError>,
+{
+    match db
+        .find_event_sequence(user_id, &event.id.to_string())
+        .await?
+    {
+        Some(sequence) => Ok(EventOutcome::Replayed { sequence }),
+        None => apply_event(user_id, event, db).await,
+    }
+}
+
+async fn apply_event<T>(user_id: i32, event: Event, db: &mut T) -> Result<EventOutcome, T::Error>
+where
+    T: EventDb<Error = EventDbError>,
+{
+    let payload = serde_json::to_value(&event.data).expect("event data serializes to json");
+    let event_type = payload["type"].as_str().unwrap_or_default().to_string();
+    let sequence = db
+        .record_event(EventRecord {
+            event_id: event.id.to_string(),
+            user_id,
+            event_type,
+            payload,
+            schema_version: event.schema_version,
+            hlc: event.hlc.encode(),
+        })
+        .await?;
+
+    let clock = &event.hlc;
+    let applied = match event.data {
+        EventData::CreateProject(data) => db.handle_create_project(user_id, data).await,
+        EventData::UpdateProjectTitle(data) => {
+            db.handle_update_project_title(user_id, data, clock).await
+        }
+        EventData::SetProjectCompleted(data) => {
+            db.handle_set_project_completed(user_id, data, clock).await
+        }
+        EventData::DeleteProject(data) => db.handle_delete_project(user_id, data).await,
+        EventData::CreateSection(data) => db.handle_create_section(user_id, data).await,
+        EventData::RenameSection(data) => db.handle_rename_section(user_id, data, clock).await,
+        EventData::DeleteSection(data) => db.handle_delete_section(user_id, data).await,
+        EventData::CreateSectionItem(data) => db.handle_create_section_item(user_id, data).await,
+        EventData::UpdateSectionItemText(data) => {
+            db.handle_update_section_item_text(user_id, data, clock)
+                .await
+        }
+        EventData::SetSectionItemCompleted(data) => {
+            db.handle_set_section_item_completed(user_id, data, clock)
+                .await
+        }
+        EventData::DeleteSectionItem(data) => db.handle_delete_section_item(user_id, data).await,
+        EventData::ReorderSectionItems(data) => {
+            db.handle_reorder_section_items(user_id, data).await
+        }
+        EventData::AddSectionItemNote(data) => db.handle_add_section_item_note(user_id, data).await,
+        EventData::EditSectionItemNote(data) => {
+            db.handle_edit_section_item_note(user_id, data, clock).await
+        }
+        EventData::RemoveSectionItemNote(data) => {
+            db.handle_remove_section_item_note(user_id, data).await
+        }
+    };
+
+    match applied {
+        Ok(()) => Ok(EventOutcome::Applied { sequence }),
+        // nothing was written besides the log entry, so the rest of the batch can go ahead
+        Err(EventDbError::Superseded { .. }) => Ok(EventOutcome::Superseded { sequence }),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deserialize_event(event: ByteString) -> Result<Event, DeserializeEventError> {
+        decode_event(
+            serde_json::from_slice(event.as_bytes()).unwrap(),
+            &Upcasters::new(),
+            &ServerClock::new(),
+        )
+    }
+
+    #[test]
+    fn nack_for_unparsable_event() {
+        let err = deserialize_event(ByteString::from_static(r#"{"type": "Unknown"}"#)).unwrap_err();
+        let message = ServerMessage::Nack {
+            event_id: None,
+            code: RejectionCode::InvalidEvent,
+            reason: err.to_string(),
+        };
+
+        let json: serde_json::Value = serde_json::from_str(&message.to_json()).unwrap();
+        assert_eq!(json["type"], "nack");
+        assert_eq!(json["code"], "invalid_event");
+        assert!(json["event_id"].is_null());
+    }
+
+    #[test]
+    fn deserialize_client_event_id() {
+        let event = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 1,
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            event.id(),
+            Uuid::parse_str("7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21").unwrap()
+        );
+    }
+
+    #[test]
+    fn deserialize_client_clock() {
+        let event = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 1,
+                "hlc": {"wall_time": 1765000000000, "counter": 2, "node": "ipad"},
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ))
+        .unwrap();
+
+        assert_eq!(event.hlc, Hlc::new(1765000000000, 2, "ipad"));
+    }
+
+    #[test]
+    fn server_clock_for_event_without_hlc() {
+        let event = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 1,
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ))
+        .unwrap();
+
+        assert!(event.hlc > Hlc::new(1765000000000, 0, "ipad"));
+    }
+
+    #[test]
+    fn clockless_updates_in_the_same_millisecond_keep_their_order() {
+        let clock = ServerClock::new();
+        let update = |title: &str| {
+            serde_json::json!({
+                "id": Uuid::new_v4(),
+                "schema_version": 1,
+                "data": {"type": "UpdateProjectTitle", "project_id": 1, "title": title}
+            })
+        };
+
+        // back to back, so almost always within one millisecond
+        let first = decode_event(update("Quilt"), &Upcasters::new(), &clock).unwrap();
+        let second = decode_event(update("Baby quilt"), &Upcasters::new(), &clock).unwrap();
+
+        assert!(first.hlc < second.hlc);
+        assert!(first.hlc.encode() < second.hlc.encode());
+    }
+
+    #[test]
+    fn clockless_event_orders_after_client_clocks_seen() {
+        let clock = ServerClock::new();
+        let ahead = Hlc::new(ServerClock::wall_time() + 60_000, 3, "ipad");
+        let event = |hlc: Option<&Hlc>| {
+            serde_json::json!({
+                "id": Uuid::new_v4(),
+                "schema_version": 1,
+                "hlc": hlc,
+                "data": {"type": "DeleteProject", "project_id": 1}
+            })
+        };
+
+        decode_event(event(Some(&ahead)), &Upcasters::new(), &clock).unwrap();
+        let clockless = decode_event(event(None), &Upcasters::new(), &clock).unwrap();
+
+        assert!(clockless.hlc > ahead);
+    }
+
+    #[test]
+    fn reject_clock_far_ahead_of_server() {
+        let err = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 1,
+                "hlc": {"wall_time": 4102444800000, "counter": 0, "node": "ipad"},
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ))
+        .unwrap_err();
+
+        assert_eq!(err.code(), RejectionCode::ClockAhead);
+        assert!(err.event_id().is_some());
+    }
+
+    #[test]
+    fn reject_oversized_clock_node() {
+        let event = serde_json::json!({
+            "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+            "schema_version": 1,
+            "hlc": {"wall_time": 1765000000000u64, "counter": 0, "node": "n".repeat(300)},
+            "data": {"type": "DeleteProject", "project_id": 1}
+        });
+
+        let err = decode_event(event, &Upcasters::new(), &ServerClock::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeEventError::NodeTooLong { length: 300, .. }
+        ));
+        assert_eq!(err.code(), RejectionCode::InvalidEvent);
+    }
+
+    #[test]
+    fn reject_event_without_uuid() {
+        let result = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "1",
+                "schema_version": 1,
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_event_without_schema_version() {
+        let result = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_event_from_unsupported_schema_version() {
+        let result = deserialize_event(ByteString::from_static(
+            r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 0,
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+        ));
+
+        let err = result.unwrap_err();
+        assert_eq!(err.code(), RejectionCode::UnsupportedSchemaVersion);
+        assert!(err.event_id().is_some());
+    }
+
+    const BATCH: &str = r#"{
+        "batch_id": "0b7c2a1e-3f4d-4c5b-8a9e-6d7f8e9a0b1c",
+        "events": [
+            {
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 1,
+                "data": {"type": "CreateProject", "project_id": 4, "title": "Tote bag"}
+            },
+            {
+                "id": "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f",
+                "schema_version": 1,
+                "data": {"type": "SetProjectCompleted", "project_id": 4, "completed": true}
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn deserialize_batch_in_order() {
+        let message = deserialize_message(
+            ByteString::from_static(BATCH),
+            &Upcasters::new(),
+            &ServerClock::new(),
+        )
+        .unwrap();
+
+        let ClientMessage::Batch { events, .. } = message else {
+            panic!("expected a batch");
+        };
+        let ids: Vec<String> = events.iter().map(|event| event.id().to_string()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f"
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_single_event_message() {
+        let message = deserialize_message(
+            ByteString::from_static(
+                r#"{
+                "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "schema_version": 1,
+                "data": {"type": "DeleteProject", "project_id": 1}
+            }"#,
+            ),
+            &Upcasters::new(),
+            &ServerClock::new(),
+        )
+        .unwrap();
+
+        assert!(matches!(message, ClientMessage::Event(_)));
+    }
+
+    #[test]
+    fn nack_whole_batch_for_invalid_event() {
+        let batch = BATCH.replace(r#""completed": true"#, r#""completed": "yes""#);
+        let err = deserialize_message(
+            ByteString::from(batch),
+            &Upcasters::new(),
+            &ServerClock::new(),
+        )
+        .unwrap_err();
+
+        let json: serde_json::Value = serde_json::from_str(&err.nack().to_json()).unwrap();
+        assert_eq!(json["type"], "batch_nack");
+        assert_eq!(json["batch_id"], "0b7c2a1e-3f4d-4c5b-8a9e-6d7f8e9a0b1c");
+        assert_eq!(json["event_id"], "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f");
+        assert_eq!(json["code"], "invalid_event");
+    }
+
+    #[test]
+    fn reject_oversized_batch() {
+        let event = serde_json::json!({
+            "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+            "schema_version": 1,
+            "data": {"type": "DeleteProject", "project_id": 1}
+        });
+        let batch = serde_json::json!({
+            "batch_id": "0b7c2a1e-3f4d-4c5b-8a9e-6d7f8e9a0b1c",
+            "events": vec![event; MAX_BATCH_SIZE + 1],
+        });
+
+        let err = deserialize_message(
+            ByteString::from(batch.to_string()),
+            &Upcasters::new(),
+            &ServerClock::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeMessageError::BatchTooLarge { size, .. } if size == MAX_BATCH_SIZE + 1
+        ));
+    }
+
+    fn to_cbor(value: &serde_json::Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn deserialize_cbor_batch() {
+        let batch: serde_json::Value = serde_json::from_str(BATCH).unwrap();
+
+        let message =
+            deserialize_cbor_message(&to_cbor(&batch), &Upcasters::new(), &ServerClock::new())
+                .unwrap();
+        let ClientMessage::Batch { batch_id, events } = message else {
+            panic!("expected a batch");
+        };
+        assert_eq!(batch_id.to_string(), "0b7c2a1e-3f4d-4c5b-8a9e-6d7f8e9a0b1c");
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn nack_for_invalid_cbor() {
+        let err = deserialize_cbor_message(&[0xff, 0x00], &Upcasters::new(), &ServerClock::new())
+            .unwrap_err();
+
+        let json: serde_json::Value = serde_json::from_str(&err.nack().to_json()).unwrap();
+        assert_eq!(json["type"], "nack");
+        assert_eq!(json["code"], "invalid_event");
+    }
+
+    #[test]
+    fn cbor_ack_matches_json() {
+        let message = ServerMessage::Ack {
+            event_id: Uuid::parse_str("7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21").unwrap(),
+            sequence: 4,
+            superseded: false,
+        };
+
+        let decoded: serde_json::Value = ciborium::from_reader(&message.to_cbor()[..]).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&message.to_json()).unwrap();
+        assert_eq!(decoded, json);
+    }
+
+    #[test]
+    fn supported_schema_versions() {
+        let upcasters = Upcasters::new();
+
+        assert!(upcasters.is_supported(SCHEMA_VERSION));
+        assert!(!upcasters.is_supported(MIN_SUPPORTED_SCHEMA_VERSION - 1));
+        assert!(!upcasters.is_supported(SCHEMA_VERSION + 1));
+    }
+
+    // historic payloads, as older app versions sent them
+    const CREATE_PROJECT_WITH_NAME: &str =
+        r#"{"type": "CreateProject", "user_id": 1, "project_id": 4, "name": "Tote bag"}"#;
+    const CHECK_ITEM: &str = r#"{"type": "CheckItem", "user_id": 1, "item_id": 9}"#;
+
+    fn rename_name_to_title(
+        mut data: serde_json::Value,
+    ) -> Result<serde_json::Value, serde_json::Error> {
+        let name = data["name"].take();
+        data["title"] = name;
+        data.as_object_mut().unwrap().remove("name");
+        Ok(data)
+    }
+
+    fn check_item_to_set_completed(
+        data: serde_json::Value,
+    ) -> Result<serde_json::Value, serde_json::Error> {
+        Ok(serde_json::json!({
+            "type": "SetSectionItemCompleted",
+            "item_id": data["item_id"],
+            "is_complete": true,
+        }))
+    }
+
+    /// A client frame carrying `fixture` as the data of an event from `schema_version`.
+    fn frame(schema_version: i32, fixture: &str) -> ByteString {
+        let data: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        let event = serde_json::json!({
+            "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+            "schema_version": schema_version,
+            "data": data,
+        });
+        ByteString::from(event.to_string())
+    }
+
+    fn decoded_data(message: ClientMessage) -> serde_json::Value {
+        let ClientMessage::Event(event) = message else {
+            panic!("expected a single event");
+        };
+        serde_json::to_value(event.data).unwrap()
+    }
+
+    #[test]
+    fn upcast_renamed_field() {
+        // the server is on version 2 and the client still writes version 1 events
+        let upcasters =
+            Upcasters::for_versions(1, 2).register("CreateProject", 1, rename_name_to_title);
+
+        let message = deserialize_message(
+            frame(1, CREATE_PROJECT_WITH_NAME),
+            &upcasters,
+            &ServerClock::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            decoded_data(message),
+            serde_json::json!({
+                "type": "CreateProject",
+                "project_id": 4,
+                "title": "Tote bag",
+            })
+        );
+    }
+
+    #[test]
+    fn upcast_through_several_versions() {
+        let upcasters = Upcasters::for_versions(1, 3)
+            .register("CheckItem", 1, check_item_to_set_completed)
+            .register("SetSectionItemCompleted", 2, |mut data| {
+                data["is_complete"] = serde_json::json!(true);
+                Ok(data)
+            });
+
+        let message =
+            deserialize_message(frame(1, CHECK_ITEM), &upcasters, &ServerClock::new()).unwrap();
+        assert_eq!(
+            decoded_data(message),
+            serde_json::json!({
+                "type": "SetSectionItemCompleted",
+                "item_id": 9,
+                "is_complete": true,
+            })
+        );
+    }
+
+    #[test]
+    fn upcast_without_step_rejects_outgrown_shape() {
+        let upcasters = Upcasters::for_versions(1, 2);
+
+        let err = deserialize_message(
+            frame(1, CREATE_PROJECT_WITH_NAME),
+            &upcasters,
+            &ServerClock::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.nack(),
+            ServerMessage::Nack {
+                code: RejectionCode::InvalidEvent,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn reject_events_outside_registry_versions() {
+        let upcasters =
+            Upcasters::for_versions(2, 3).register("CreateProject", 1, rename_name_to_title);
+
+        for schema_version in [1, 4] {
+            let err = deserialize_message(
+                frame(schema_version, CREATE_PROJECT_WITH_NAME),
+                &upcasters,
+                &ServerClock::new(),
+            )
+            .unwrap_err();
+            assert!(matches!(
+                err.nack(),
+                ServerMessage::Nack {
+                    code: RejectionCode::UnsupportedSchemaVersion,
+                    ..
+                }
+            ));
+        }
+    }
+
+    type HandlerResultFn = Box<dyn Fn(&str) -> Result<(), EventDbError>>;
+    type RollbackResultFn = Box<dyn Fn() -> Result<(), EventDbError>>;
+
+    /// Keeps what a transaction wrote apart from what was committed, so tests can tell
+    /// whether a rolled back batch left anything behind. Each handler records its name and
+    /// returns whatever the builder's `with_handler_result` gives for it, and the user it ran
+    /// for is kept in `owner_ids`.
+    struct MockEventDb {
+        handler_result: HandlerResultFn,
+        rollback_result: RollbackResultFn,
+        in_transaction: bool,
+        rolled_back: bool,
+        pending: Vec<String>,
+        committed: Vec<String>,
+        owner_ids: Vec<i32>,
+        sequence: i64,
+    }
+
+    #[derive(Default)]
+    struct MockEventDbBuilder {
+        handler_result: Option<HandlerResultFn>,
+        rollback_result: Option<RollbackResultFn>,
+    }
+
+    impl MockEventDb {
+        fn builder() -> MockEventDbBuilder {
+            MockEventDbBuilder::default()
+        }
+
+        fn apply(&mut self, change: &str) {
+            assert!(self.in_transaction, "{change} outside a transaction");
+            self.pending.push(change.to_string());
+        }
+
+        fn handle(&mut self, handler: &str, owner_id: i32) -> Result<(), EventDbError> {
+            self.apply(handler);
+            self.owner_ids.push(owner_id);
+            (self.handler_result)(handler)
+        }
+    }
+
+    impl Default for MockEventDb {
+        fn default() -> Self {
+            MockEventDb::builder().build()
+        }
+    }
+
+    impl MockEventDbBuilder {
+        fn with_handler_result<F>(mut self, f: F) -> Self
+        where
+            F: Fn(&str) -> Result<(), EventDbError> + 'static,
+        {
+            self.handler_result = Some(Box::new(f));
+            self
+        }
+
+        fn with_rollback_result<F>(mut self, f: F) -> Self
+        where
+            F: Fn() -> Result<(), EventDbError> + 'static,
+        {
+            self.rollback_result = Some(Box::new(f));
+            self
+        }
+
+        fn build(self) -> MockEventDb {
+            MockEventDb {
+                handler_result: self.handler_result.unwrap_or_else(|| Box::new(|_| Ok(()))),
+                rollback_result: self.rollback_result.unwrap_or_else(|| Box::new(|| Ok(()))),
+                in_transaction: false,
+                rolled_back: false,
+                pending: Vec::new(),
+                committed: Vec::new(),
+                owner_ids: Vec::new(),
+                sequence: 0,
+            }
+        }
+    }
+
+    /// Implements each event handler as a call to [`MockEventDb::handle`] with its name.
+    macro_rules! mock_handlers {
+        ($($handler:ident($data:ty $(, $clock:ident)?)),* $(,)?) => {
+            $(
+                async fn $handler(
+                    &mut self,
+                    owner_id: i32,
+                    _data: $data,
+                    $($clock: &Hlc,)?
+                ) -> Result<(), EventDbError> {
+                    self.handle(stringify!($handler), owner_id)
+                }
+            )*
+        };
+    }
+
+    impl EventDb for MockEventDb {
+        type Error = EventDbError;
+
+        async fn begin_transaction(&mut self) -> Result<(), EventDbError> {
+            self.in_transaction = true;
+            Ok(())
+        }
+
+        async fn commit_transaction(&mut self) -> Result<(), EventDbError> {
+            self.in_transaction = false;
+            self.committed.append(&mut self.pending);
+            Ok(())
+        }
+
+        async fn rollback_transaction(&mut self) -> Result<(), EventDbError> {
+            (self.rollback_result)()?;
+            self.in_transaction = false;
+            self.rolled_back = true;
+            self.pending.clear();
+            Ok(())
+        }
+
+        async fn lock_user_changes(&mut self, _user_id: i32) -> Result<(), EventDbError> {
+            Ok(())
+        }
+
+        async fn find_event_sequence(
+            &mut self,
+            _user_id: i32,
+            _event_id: &str,
+        ) -> Result<Option<i64>, EventDbError> {
+            Ok(None)
+        }
+
+        async fn record_event(&mut self, record: EventRecord) -> Result<i64, EventDbError> {
+            self.apply(&format!("record {}", record.event_id));
+            self.sequence += 1;
+            Ok(self.sequence)
+        }
+
+        mock_handlers!(
+            handle_create_project(CreateProjectEventData),
+            handle_update_project_title(UpdateProjectTitleEventData, _clock),
+            handle_set_project_completed(SetProjectCompletedEventData, _clock),
+            handle_delete_project(DeleteProjectEventData),
+            handle_create_section(CreateSectionEventData),
+            handle_rename_section(RenameSectionEventData, _clock),
+            handle_delete_section(DeleteSectionEventData),
+            handle_create_section_item(CreateSectionItemEventData),
+            handle_update_section_item_text(UpdateSectionItemTextEventData, _clock),
+            handle_set_section_item_completed(SetSectionItemCompletedEventData, _clock),
+            handle_delete_section_item(DeleteSectionItemEventData),
+            handle_reorder_section_items(ReorderSectionItemsEventData),
+            handle_add_section_item_note(AddSectionItemNoteEventData),
+            handle_edit_section_item_note(EditSectionItemNoteEventData, _clock),
+            handle_remove_section_item_note(RemoveSectionItemNoteEventData),
+        );
+    }
+
+    fn test_event(id: &str, data: serde_json::Value) -> Event {
+        decode_event(
+            serde_json::json!({"id": id, "schema_version": 1, "data": data}),
+            &Upcasters::new(),
+            &ServerClock::new(),
+        )
+        .unwrap()
+    }
+
+    fn create_project_event(id: &str) -> Event {
+        test_event(
+            id,
+            serde_json::json!({"type": "CreateProject", "project_id": 1, "title": "Quilt"}),
+        )
+    }
+
+    #[actix_web::test]
+    async fn events_change_the_connection_users_rows() {
+        let mut db = MockEventDb::default();
+        // older clients still say who the change is for, which is ignored
+        let event = test_event(
+            "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+            serde_json::json!({"type": "CreateProject", "user_id": 2, "project_id": 1, "title": "Quilt"}),
+        );
+
+        let outcome = handle_event(1, event, &mut db).await.unwrap();
+
+        assert_eq!(outcome, EventOutcome::Applied { sequence: 1 });
+        assert_eq!(db.owner_ids, vec![1]);
+    }
+
+    #[actix_web::test]
+    async fn reject_batch_with_event_under_another_users_row() {
+        let mut db = MockEventDb::builder()
+            .with_handler_result(|handler| match handler {
+                "handle_create_section" => Err(EventDbError::NotOwned { entity: "project" }),
+                _ => Ok(()),
+            })
+            .build();
+        let events = vec![
+            create_project_event("7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21"),
+            test_event(
+                "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f",
+                serde_json::json!({"type": "CreateSection", "project_id": 5, "section_id": 1, "name": "Binding"}),
+            ),
+        ];
+
+        let err = handle_batch(1, events, &mut db).await.unwrap_err();
+        let batch_id = Uuid::parse_str("0b7c2a1e-3f4d-4c5b-8a9e-6d7f8e9a0b1c").unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&err.nack(batch_id).to_json()).unwrap();
+        assert_eq!(json["type"], "batch_nack");
+        assert_eq!(json["event_id"], "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f");
+        assert_eq!(json["code"], "wrong_user");
+        assert!(db.rolled_back);
+        assert!(db.pending.is_empty());
+        assert!(db.committed.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn roll_back_whole_batch_when_an_event_fails() {
+        let mut db = MockEventDb::builder()
+            .with_handler_result(|handler| match handler {
+                "handle_set_project_completed" => Err(EventDbError::NotFound { entity: "project" }),
+                _ => Ok(()),
+            })
+            .build();
+        let events = vec![
+            create_project_event("7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21"),
+            test_event(
+                "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f",
+                serde_json::json!({"type": "SetProjectCompleted", "project_id": 1, "completed": true}),
+            ),
+        ];
+
+        let err = handle_batch(1, events, &mut db).await.unwrap_err();
+
+        assert_eq!(
+            err.event_id.map(|id| id.to_string()).as_deref(),
+            Some("2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f")
+        );
+        assert_eq!(RejectionCode::from(&err.source), RejectionCode::NotFound);
+        assert!(db.rolled_back);
+        assert!(db.committed.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn superseded_event_does_not_fail_the_batch() {
+        let mut db = MockEventDb::builder()
+            .with_handler_result(|handler| match handler {
+                "handle_update_project_title" => Err(EventDbError::Superseded {
+                    field: "project title",
+                }),
+                _ => Ok(()),
+            })
+            .build();
+        let events = vec![
+            create_project_event("7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21"),
+            test_event(
+                "2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f",
+                serde_json::json!({"type": "UpdateProjectTitle", "project_id": 1, "title": "Baby quilt"}),
+            ),
+            test_event(
+                "5a8b9c0d-1e2f-4a3b-9c4d-5e6f7a8b9c0d",
+                serde_json::json!({"type": "SetProjectCompleted", "project_id": 1, "completed": true}),
+            ),
+        ];
+
+        let outcomes = handle_batch(1, events, &mut db).await.unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                EventOutcome::Applied { sequence: 1 },
+                EventOutcome::Superseded { sequence: 2 },
+                EventOutcome::Applied { sequence: 3 },
+            ]
+        );
+        assert!(!db.rolled_back);
+        assert_eq!(
+            db.committed,
+            vec![
+                "record 7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+                "handle_create_project",
+                "record 2e6f0c3b-9a1d-4b7e-8c5f-4a3b2c1d0e9f",
+                "handle_update_project_title",
+                "record 5a8b9c0d-1e2f-4a3b-9c4d-5e6f7a8b9c0d",
+                "handle_set_project_completed",
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn failed_rollback_keeps_original_error_as_context() {
+        let mut db = MockEventDb::builder()
+            .with_handler_result(|_| Err(EventDbError::NotFound { entity: "project" }))
+            .with_rollback_result(|| Err(diesel::result::Error::BrokenTransactionManager.into()))
+            .build();
+        let event = create_project_event("7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21");
+
+        let err = handle_batch(1, vec![event], &mut db).await.unwrap_err();
+
+        assert!(err.event_id.is_some());
+        let HandleEventError::Rollback { original, .. } = &err.source else {
+            panic!("expected a rollback error, got {:?}", err.source);
+        };
+        assert!(matches!(
+            **original,
+            HandleEventError::Database {
+                source: EventDbError::NotFound { entity: "project" }
+            }
+        ));
+        assert_eq!(
+            RejectionCode::from(&err.source),
+            RejectionCode::InternalError
+        );
+    }
+
+    #[test]
+    fn rejection_code_for_missing_parent() {
+        let err = EventDbError::Database {
+            location: snafu::location!(),
+            source: diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+                Box::new("violates foreign key constraint".to_string()),
+            ),
+        };
+
+        assert_eq!(RejectionCode::from(&err), RejectionCode::NotFound);
     }
 }
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+/// How often the server pings an open websocket and how many pongs in a row it may miss
+/// before the connection is treated as dead.
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub max_missed_pongs: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_missed_pongs: 2,
+        }
+    }
+}
+
+/// Counts the pings a connection hasn't answered yet.
+pub(crate) struct Heartbeat {
+    unanswered: u32,
+    max_missed_pongs: u32,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(config: &HeartbeatConfig) -> Self {
+        Self {
+            unanswered: 0,
+            // a peer has to get at least one interval to answer
+            max_missed_pongs: config.max_missed_pongs.max(1),
+        }
+    }
+
+    /// Called when it's time to send the next ping. Returns false once the peer has missed
+    /// too many pongs and should be dropped instead of pinged again.
+    pub(crate) fn ping(&mut self) -> bool {
+        if self.unanswered >= self.max_missed_pongs {
+            return false;
+        }
+
+        self.unanswered += 1;
+        true
+    }
+
+    pub(crate) fn pong(&mut self) {
+        self.unanswered = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(max_missed_pongs: u32) -> Heartbeat {
+        Heartbeat::new(&HeartbeatConfig {
+            interval: Duration::from_secs(1),
+            max_missed_pongs,
+        })
+    }
+
+    #[test]
+    fn drops_after_missed_pongs() {
+        let mut heartbeat = heartbeat(2);
+
+        // a ping isn't missed until the next one is due
+        assert!(heartbeat.ping());
+        assert!(heartbeat.ping());
+        assert!(!heartbeat.ping());
+    }
+
+    #[test]
+    fn pong_resets_missed_count() {
+        let mut heartbeat = heartbeat(1);
+
+        assert!(heartbeat.ping());
+        heartbeat.pong();
+        assert!(heartbeat.ping());
+        assert!(!heartbeat.ping());
+    }
+}
@@ -1,34 +1,91 @@
+mod auth;
+mod connections;
 mod db;
 mod events;
+mod heartbeat;
+mod mailer;
+mod password_policy;
+mod rate_limit;
+mod sync;
+mod ticket;
+mod verification;
 
 use actix_session::{Session, SessionInsertError};
 use actix_web::http::{StatusCode, header};
-use actix_web::{HttpRequest, HttpResponse, Responder, get, mime, post, web};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, mime, post, web};
 use actix_web::{HttpResponseBuilder, ResponseError};
-use actix_ws::AggregatedMessage;
+use actix_ws::{AggregatedMessage, CloseCode, CloseReason};
 use auth_utils::GenerateHashError;
 use chrono::{DateTime, Utc};
+use diesel::Insertable;
+use diesel::Selectable;
 use diesel::deserialize::{self, FromSql};
 use diesel::prelude::Queryable;
 use diesel::serialize::{self, Output, ToSql};
 use diesel::sql_types::Text;
-use diesel::{ExpressionMethods, QueryDsl, QueryableByName, Selectable};
-use diesel::{Insertable, SelectableHelper};
 use diesel_async::AsyncPgConnection;
-use diesel_async::RunQueryDsl;
-use diesel_async::pg;
-use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::pooled_connection::deadpool::{Object, Pool};
 use email_address::{EmailAddress, Options};
+use event_database::ServerClock;
 use futures_util::StreamExt as _;
+use futures_util::future::{Either, select};
 use serde::Deserialize;
 use serde::Serialize;
 use snafu::Location;
 use snafu::ResultExt;
 use snafu::prelude::*;
+use sqlite_session_store::SqliteSessionStore;
 use std::io::Write;
 
 use crate::db::{DB, Database};
 
+pub use crate::auth::{AuthenticatedUser, SyncingUser};
+pub use crate::connections::ConnectionRegistry;
+pub use crate::events::{UpcastFn, Upcasters};
+pub use crate::heartbeat::HeartbeatConfig;
+pub use crate::mailer::{FileMailer, MailError, Mailer, OutgoingMail, SmtpConfig, SmtpMailer};
+pub use crate::password_policy::{PasswordPolicy, read_common_passwords};
+pub use crate::rate_limit::RateLimitConfig;
+pub use crate::sync::{snapshot, sync_table};
+pub use crate::ticket::TicketSigner;
+pub use crate::verification::VerificationPolicy;
+
+pub type SocketRegistry = ConnectionRegistry<Socket>;
+
+/// An open websocket, the app database schema version its device announced and the encoding
+/// it asked for.
+#[derive(Clone)]
+pub struct Socket {
+    session: actix_ws::Session,
+    schema_version: i32,
+    encoding: events::Encoding,
+}
+
+impl Socket {
+    /// Sends a message in the encoding the socket negotiated when it connected.
+    async fn send(&mut self, message: &events::ServerMessage) -> Result<(), actix_ws::Closed> {
+        match self.encoding {
+            events::Encoding::Json => self.session.text(message.to_json()).await,
+            events::Encoding::Cbor => self.session.binary(message.to_cbor()).await,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SocketParams {
+    schema_version: i32,
+    #[serde(default)]
+    encoding: events::Encoding,
+}
+
+/// Read from the query on its own, before [`SocketParams`], so the upgrade is authenticated
+/// before anything else about it is checked.
+#[derive(Deserialize)]
+struct TicketParams {
+    /// A ticket from `/ws/ticket`, for clients that can't send the session cookie.
+    ticket: Option<String>,
+}
+
 #[derive(
     Debug,
     Clone,
@@ -80,17 +137,13 @@ struct UserCredentials {
     password: String,
 }
 
-impl TryFrom<SignupCredentials> for UserCredentials {
-    type Error = SignupError;
-
-    fn try_from(value: SignupCredentials) -> Result<Self, Self::Error> {
+impl UserCredentials {
+    fn validate(value: SignupCredentials, policy: &PasswordPolicy) -> Result<Self, SignupError> {
         let email = EmailAddress::parse_with_options(&value.email, Options::default())
             .context(InvalidEmailSnafu {})?;
         let email = Email(email);
 
-        if value.password.is_empty() {
-            return Err(SignupError::InvalidPassword);
-        }
+        policy.check(&value.password)?;
 
         Ok(UserCredentials {
             email,
@@ -124,6 +177,15 @@ impl UserInput {
     }
 }
 
+#[derive(Insertable, Debug)]
+#[diesel(table_name = app_db::schema::api_tokens)]
+pub struct NewApiToken {
+    user_id: i32,
+    name: String,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize)]
 struct UserLogin {
     email: Email,
@@ -138,7 +200,7 @@ pub struct User {
     email: Email,
     password_hash: String,
     created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
+    verified_at: Option<DateTime<Utc>>,
 }
 
 type DbPool = Pool<AsyncPgConnection>;
@@ -146,14 +208,32 @@ type DbPool = Pool<AsyncPgConnection>;
 #[post("/signup")]
 async fn signup_endpoint(
     db_pool: web::Data<DbPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    mailer: web::Data<dyn Mailer>,
     web::Json(credentials): web::Json<SignupCredentials>,
-) -> actix_web::Result<(), SignupError> {
+) -> actix_web::Result<web::Json<SignupResponse>, SignupError> {
     let mut conn = db_pool.get().await.unwrap();
     let db = DB::new(&mut conn);
 
-    create_user_from_signup(credentials, db).await?;
+    let response = create_user_from_signup(
+        credentials,
+        db,
+        &password_policy,
+        mailer.as_ref(),
+        Utc::now(),
+    )
+    .await?;
 
-    Ok(())
+    Ok(web::Json(response))
+}
+
+/// The account is created even when its verification email can't be sent. The client should
+/// then offer to send another one from `resend_path` once the user is logged in.
+#[derive(Debug, Serialize)]
+pub struct SignupResponse {
+    pub verification_email_sent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resend_path: Option<&'static str>,
 }
 
 #[derive(Debug, Snafu)]
@@ -164,8 +244,12 @@ pub enum SignupError {
         location: Location,
         source: email_address::Error,
     },
-    #[snafu(display("Invalid password"))]
-    InvalidPassword,
+    #[snafu(display("Password must be at least {min_length} characters"))]
+    PasswordTooShort { min_length: usize },
+    #[snafu(display("Password must be at most {max_length} characters"))]
+    PasswordTooLong { max_length: usize },
+    #[snafu(display("This password is too common, choose a less guessable one"))]
+    CommonPassword,
     #[snafu(display("Create user failed"))]
     CreateUserFailed {
         #[snafu(implicit)]
@@ -184,7 +268,9 @@ impl ResponseError for SignupError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             SignupError::InvalidEmail { .. } => actix_web::http::StatusCode::BAD_REQUEST,
-            SignupError::InvalidPassword => actix_web::http::StatusCode::BAD_REQUEST,
+            SignupError::PasswordTooShort { .. }
+            | SignupError::PasswordTooLong { .. }
+            | SignupError::CommonPassword => actix_web::http::StatusCode::BAD_REQUEST,
             SignupError::CreateUserFailed { .. } => {
                 actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
             }
@@ -198,20 +284,21 @@ impl ResponseError for SignupError {
         let mut response_builder = HttpResponseBuilder::new(self.status_code());
         response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
         let message = self.to_string();
-        let response = response_builder.body(message);
-
-        response
+        response_builder.body(message)
     }
 }
 
 async fn create_user_from_signup(
     credentials: SignupCredentials,
     mut db: impl Database,
-) -> Result<(), SignupError> {
-    let credentials = UserCredentials::try_from(credentials)?;
+    password_policy: &PasswordPolicy,
+    mailer: &dyn Mailer,
+    now: DateTime<Utc>,
+) -> Result<SignupResponse, SignupError> {
+    let credentials = UserCredentials::validate(credentials, password_policy)?;
     let hash =
         auth_utils::generate_password_hash(&credentials.password).context(PasswordHashSnafu {})?;
-    let now = Utc::now();
+    let email = credentials.email.as_str().to_string();
     let user = UserInput {
         email: credentials.email,
         password_hash: hash,
@@ -219,11 +306,25 @@ async fn create_user_from_signup(
         updated_at: now,
     };
 
-    db.create_user(user)
+    let user_id = db
+        .create_user(user)
         .await
         .context(CreateUserFailedSnafu {})?;
 
-    Ok(())
+    // the account exists either way, so a failed email is reported rather than failing signup
+    match send_verification_email(user_id, email, &mut db, mailer, now).await {
+        Ok(()) => Ok(SignupResponse {
+            verification_email_sent: true,
+            resend_path: None,
+        }),
+        Err(err) => {
+            log::warn!("Failed to send verification email to user {user_id}: {err}");
+            Ok(SignupResponse {
+                verification_email_sent: false,
+                resend_path: Some("/verify-email/resend"),
+            })
+        }
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -236,6 +337,8 @@ pub enum LoginError {
         location: Location,
         source: diesel::result::Error,
     },
+    #[snafu(display("Verify your email address to log in"))]
+    Unverified,
     #[snafu(display("Internal server error. Please try again later."))]
     SessionError {
         #[snafu(implicit)]
@@ -248,6 +351,7 @@ impl ResponseError for LoginError {
     fn status_code(&self) -> StatusCode {
         match self {
             LoginError::InvalidCredentials | LoginError::UserNotFound { .. } => StatusCode::OK,
+            LoginError::Unverified => StatusCode::FORBIDDEN,
             LoginError::SessionError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -257,20 +361,526 @@ impl ResponseError for LoginError {
         response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
 
         let message = self.to_string();
-        let response = response_builder.body(message);
-
-        response
+        response_builder.body(message)
     }
 }
 
 #[post("/login")]
 async fn login(
     db_pool: web::Data<DbPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    verification_policy: web::Data<VerificationPolicy>,
     web::Json(credentials): web::Json<UserLogin>,
     session: Session,
 ) -> actix_web::Result<(), LoginError> {
     let mut conn = db_pool.get().await.unwrap();
-    let user = get_user(&credentials.email, &mut conn)
+    let mut db = DB::new(&mut conn);
+
+    let user =
+        authenticate_user(credentials, &mut db, &password_policy, &verification_policy).await?;
+
+    session.insert("user_id", user.id).context(SessionSnafu)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiTokenRequest {
+    email: Email,
+    password: String,
+    /// Lets the user tell their tokens apart, e.g. the device it was issued to.
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct IssuedApiToken {
+    name: String,
+    token: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum ApiTokenError {
+    #[snafu(transparent)]
+    Login { source: LoginError },
+    #[snafu(display("Token name must be between 1 and 255 characters"))]
+    InvalidName,
+    #[snafu(display("Internal server error. Please try again later."))]
+    CreateToken {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+}
+
+impl ResponseError for ApiTokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiTokenError::Login {
+                source: LoginError::Unverified,
+            } => StatusCode::FORBIDDEN,
+            ApiTokenError::Login { .. } => StatusCode::UNAUTHORIZED,
+            ApiTokenError::InvalidName => StatusCode::BAD_REQUEST,
+            ApiTokenError::CreateToken { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+/// Issues a named API token for clients that authenticate with `Authorization: Bearer`
+/// rather than a session cookie. The token is only ever returned here.
+#[post("/tokens")]
+async fn api_token_endpoint(
+    db_pool: web::Data<DbPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    verification_policy: web::Data<VerificationPolicy>,
+    web::Json(request): web::Json<ApiTokenRequest>,
+) -> actix_web::Result<web::Json<IssuedApiToken>, ApiTokenError> {
+    let mut conn = db_pool.get().await.unwrap();
+    let mut db = DB::new(&mut conn);
+
+    let token = issue_api_token(request, &mut db, &password_policy, &verification_policy).await?;
+
+    Ok(web::Json(token))
+}
+
+async fn issue_api_token(
+    request: ApiTokenRequest,
+    db: &mut impl Database,
+    password_policy: &PasswordPolicy,
+    verification_policy: &VerificationPolicy,
+) -> Result<IssuedApiToken, ApiTokenError> {
+    let name = request.name.trim().to_string();
+    ensure!(
+        !name.is_empty() && name.chars().count() <= 255,
+        InvalidNameSnafu
+    );
+
+    let credentials = UserLogin {
+        email: request.email,
+        password: request.password,
+    };
+    let user = authenticate_user(credentials, db, password_policy, verification_policy).await?;
+
+    let generated = auth_utils::generate_token();
+    let api_token = NewApiToken {
+        user_id: user.id,
+        name: name.clone(),
+        token_hash: generated.token_hash,
+        created_at: Utc::now(),
+    };
+    db.create_api_token(api_token)
+        .await
+        .context(CreateTokenSnafu)?;
+
+    Ok(IssuedApiToken {
+        name,
+        token: generated.token,
+    })
+}
+
+#[derive(Debug, Snafu)]
+pub enum LogoutError {
+    #[snafu(display("Failed to end the user's sessions"))]
+    DeleteSessions {
+        #[snafu(source(from(anyhow::Error, Into::into)))]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[snafu(display("Failed to revoke the user's API tokens and connection tickets"))]
+    RevokeTokens {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+}
+
+impl ResponseError for LogoutError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+/// Ends the current session. The session middleware deletes it from the store.
+#[post("/logout")]
+async fn logout(session: Session) -> HttpResponse {
+    session.purge();
+    HttpResponse::Ok().finish()
+}
+
+/// Ends every session the user has and revokes their API tokens, on every device, and closes
+/// their open websockets.
+#[post("/logout/all")]
+async fn logout_everywhere(
+    db_pool: web::Data<DbPool>,
+    session_store: web::Data<SqliteSessionStore>,
+    registry: web::Data<SocketRegistry>,
+    user: AuthenticatedUser,
+    session: Session,
+) -> actix_web::Result<(), LogoutError> {
+    let user_id = user.user_id;
+
+    let mut conn = db_pool.get().await.unwrap();
+    let mut db = DB::new(&mut conn);
+    db.delete_api_tokens(user_id)
+        .await
+        .context(RevokeTokensSnafu)?;
+    db.revoke_connection_tickets(user_id)
+        .await
+        .context(RevokeTokensSnafu)?;
+    session_store
+        .delete_user_sessions(user_id)
+        .await
+        .context(DeleteSessionsSnafu)?;
+    // the current session was deleted with the rest, this drops the cookie as well
+    session.purge();
+
+    close_user_sockets(&registry, user_id).await;
+
+    Ok(())
+}
+
+/// Closes every websocket the user has open, for when their sessions have been ended.
+async fn close_user_sockets(registry: &SocketRegistry, user_id: i32) {
+    for socket in registry.remove_user(user_id).await {
+        let reason = CloseReason {
+            code: CloseCode::Policy,
+            description: Some("Logged out".to_string()),
+        };
+        // fails if the socket is already closing, which is fine
+        let _ = socket.session.close(Some(reason)).await;
+    }
+}
+
+/// How long a password reset email stays good for.
+const PASSWORD_RESET_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = app_db::schema::password_resets)]
+pub struct NewPasswordReset {
+    user_id: i32,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PasswordResetRequest {
+    email: Email,
+}
+
+#[derive(Debug, Deserialize)]
+struct PasswordResetConfirmation {
+    token: String,
+    password: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum PasswordResetError {
+    #[snafu(display("The reset link is invalid or has expired"))]
+    InvalidResetToken,
+    #[snafu(transparent)]
+    NewPassword { source: SignupError },
+    #[snafu(display("Internal server error. Please try again later."))]
+    ResetPasswordHash {
+        #[snafu(implicit)]
+        location: Location,
+        source: GenerateHashError,
+    },
+    #[snafu(display("Internal server error. Please try again later."))]
+    ResetQuery {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+    #[snafu(display("Internal server error. Please try again later."))]
+    ResetMail {
+        #[snafu(implicit)]
+        location: Location,
+        source: MailError,
+    },
+    #[snafu(display("Internal server error. Please try again later."))]
+    EndSessions {
+        #[snafu(source(from(anyhow::Error, Into::into)))]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl ResponseError for PasswordResetError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PasswordResetError::InvalidResetToken => StatusCode::BAD_REQUEST,
+            PasswordResetError::NewPassword { source } => source.status_code(),
+            PasswordResetError::ResetPasswordHash { .. }
+            | PasswordResetError::ResetQuery { .. }
+            | PasswordResetError::ResetMail { .. }
+            | PasswordResetError::EndSessions { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+/// Emails a password reset token to the user. Responds the same whether or not the email
+/// belongs to an account, so it can't be used to find out who has one.
+#[post("/password-reset")]
+async fn request_password_reset(
+    db_pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    web::Json(request): web::Json<PasswordResetRequest>,
+) -> actix_web::Result<(), PasswordResetError> {
+    let mut conn = db_pool.get().await.unwrap();
+    let mut db = DB::new(&mut conn);
+
+    start_password_reset(request, &mut db, mailer.as_ref(), Utc::now()).await
+}
+
+async fn start_password_reset(
+    request: PasswordResetRequest,
+    db: &mut impl Database,
+    mailer: &dyn Mailer,
+    now: DateTime<Utc>,
+) -> Result<(), PasswordResetError> {
+    let user = match db.get_user(&request.email).await {
+        Ok(user) => user,
+        Err(diesel::result::Error::NotFound) => return Ok(()),
+        Err(source) => return Err(source).context(ResetQuerySnafu),
+    };
+
+    let generated = auth_utils::generate_token();
+    let reset = NewPasswordReset {
+        user_id: user.id,
+        token_hash: generated.token_hash,
+        created_at: now,
+        expires_at: now + PASSWORD_RESET_TTL,
+    };
+    db.create_password_reset(reset)
+        .await
+        .context(ResetQuerySnafu)?;
+
+    let mail = OutgoingMail {
+        to: user.email.as_str().to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Use this code to reset your password: {}\n\n\
+             It expires in {} minutes. If you didn't ask to reset your password you can ignore \
+             this email.",
+            generated.token,
+            PASSWORD_RESET_TTL.num_minutes()
+        ),
+    };
+    mailer.send(mail).await.context(ResetMailSnafu)
+}
+
+/// Sets a new password with a token from a reset email, then logs the user out everywhere
+/// since whoever had the old password shouldn't stay signed in.
+#[post("/password-reset/confirm")]
+async fn confirm_password_reset(
+    db_pool: web::Data<DbPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    session_store: web::Data<SqliteSessionStore>,
+    registry: web::Data<SocketRegistry>,
+    web::Json(confirmation): web::Json<PasswordResetConfirmation>,
+) -> actix_web::Result<(), PasswordResetError> {
+    let mut conn = db_pool.get().await.unwrap();
+    let mut db = DB::new(&mut conn);
+
+    let user_id =
+        finish_password_reset(confirmation, &mut db, &password_policy, Utc::now()).await?;
+
+    session_store
+        .delete_user_sessions(user_id)
+        .await
+        .context(EndSessionsSnafu)?;
+    close_user_sockets(&registry, user_id).await;
+
+    Ok(())
+}
+
+/// Returns the user whose password was reset.
+async fn finish_password_reset(
+    confirmation: PasswordResetConfirmation,
+    db: &mut impl Database,
+    password_policy: &PasswordPolicy,
+    now: DateTime<Utc>,
+) -> Result<i32, PasswordResetError> {
+    password_policy.check(&confirmation.password)?;
+    let password_hash = auth_utils::generate_password_hash(&confirmation.password)
+        .context(ResetPasswordHashSnafu)?;
+
+    let token_hash = auth_utils::hash_token(&confirmation.token);
+    db.reset_password(&token_hash, password_hash, now)
+        .await
+        .context(ResetQuerySnafu)?
+        .context(InvalidResetTokenSnafu)
+}
+
+/// How long an email verification code stays good for.
+const EMAIL_VERIFICATION_TTL: chrono::Duration = chrono::Duration::hours(48);
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = app_db::schema::email_verifications)]
+pub struct NewEmailVerification {
+    user_id: i32,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmailVerificationConfirmation {
+    token: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum VerificationError {
+    #[snafu(display("The verification code is invalid or has expired"))]
+    InvalidVerificationToken,
+    #[snafu(display("Internal server error. Please try again later."))]
+    VerificationQuery {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+    #[snafu(display("Internal server error. Please try again later."))]
+    VerificationMail {
+        #[snafu(implicit)]
+        location: Location,
+        source: MailError,
+    },
+}
+
+impl ResponseError for VerificationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            VerificationError::InvalidVerificationToken => StatusCode::BAD_REQUEST,
+            VerificationError::VerificationQuery { .. }
+            | VerificationError::VerificationMail { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+async fn send_verification_email(
+    user_id: i32,
+    email: String,
+    db: &mut impl Database,
+    mailer: &dyn Mailer,
+    now: DateTime<Utc>,
+) -> Result<(), VerificationError> {
+    let generated = auth_utils::generate_token();
+    let verification = NewEmailVerification {
+        user_id,
+        token_hash: generated.token_hash,
+        created_at: now,
+        expires_at: now + EMAIL_VERIFICATION_TTL,
+    };
+    db.create_email_verification(verification)
+        .await
+        .context(VerificationQuerySnafu)?;
+
+    let mail = OutgoingMail {
+        to: email,
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Use this code to confirm your email address: {}\n\nIt expires in {} hours.",
+            generated.token,
+            EMAIL_VERIFICATION_TTL.num_hours()
+        ),
+    };
+    mailer.send(mail).await.context(VerificationMailSnafu)
+}
+
+/// Marks the user's email address as verified with the code from their verification email.
+#[post("/verify-email")]
+async fn verify_email(
+    db_pool: web::Data<DbPool>,
+    web::Json(confirmation): web::Json<EmailVerificationConfirmation>,
+) -> actix_web::Result<HttpResponse, VerificationError> {
+    let mut conn = db_pool.get().await.unwrap();
+    let mut db = DB::new(&mut conn);
+
+    confirm_email(confirmation, &mut db, Utc::now()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns the user whose email address was verified.
+async fn confirm_email(
+    confirmation: EmailVerificationConfirmation,
+    db: &mut impl Database,
+    now: DateTime<Utc>,
+) -> Result<i32, VerificationError> {
+    let token_hash = auth_utils::hash_token(&confirmation.token);
+    db.verify_email(&token_hash, now)
+        .await
+        .context(VerificationQuerySnafu)?
+        .context(InvalidVerificationTokenSnafu)
+}
+
+/// Sends the logged in user a fresh verification email, for when the first one got lost or
+/// expired. Does nothing if they're already verified.
+#[post("/verify-email/resend")]
+async fn resend_verification_email(
+    db_pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    user: AuthenticatedUser,
+) -> actix_web::Result<(), VerificationError> {
+    let mut conn = db_pool.get().await.unwrap();
+    let mut db = DB::new(&mut conn);
+
+    let user = db
+        .get_user_by_id(user.user_id)
+        .await
+        .context(VerificationQuerySnafu)?;
+    if user.verified_at.is_some() {
+        return Ok(());
+    }
+
+    let email = user.email.as_str().to_string();
+    send_verification_email(user.id, email, &mut db, mailer.as_ref(), Utc::now()).await
+}
+
+async fn authenticate_user(
+    credentials: UserLogin,
+    db: &mut impl Database,
+    password_policy: &PasswordPolicy,
+    verification_policy: &VerificationPolicy,
+) -> Result<User, LoginError> {
+    // no password this long could have been set, and hashing it would only burn CPU
+    ensure!(
+        !password_policy.is_too_long(&credentials.password),
+        InvalidCredentialsSnafu
+    );
+
+    let user = db
+        .get_user(&credentials.email)
         .await
         .context(UserNotFoundSnafu)?;
 
@@ -278,78 +888,373 @@ async fn login(
     if let auth_utils::PasswordVerify::NoMatch = result {
         return Err(LoginError::InvalidCredentials);
     }
+    ensure!(
+        verification_policy.may_log_in(user.created_at, user.verified_at, Utc::now()),
+        UnverifiedSnafu
+    );
 
-    session.insert("user_id", user.id).context(SessionSnafu)?;
+    Ok(user)
+}
 
-    Ok(())
+#[derive(Debug, Snafu)]
+pub enum WebsocketAuthError {
+    #[snafu(transparent)]
+    Auth { source: auth::AuthError },
+    #[snafu(display("Invalid connection ticket: {source}"))]
+    InvalidTicket { source: ticket::TicketError },
+}
+
+impl ResponseError for WebsocketAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebsocketAuthError::Auth { source } => source.status_code(),
+            WebsocketAuthError::InvalidTicket { .. } => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
+        if let WebsocketAuthError::Auth { source } = self {
+            return source.error_response();
+        }
+
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+/// Mints a short-lived ticket for opening a websocket as the logged in user, for clients that
+/// can't attach a session cookie or an `Authorization` header to the upgrade request.
+#[post("/ws/ticket")]
+async fn websocket_ticket(
+    signer: web::Data<TicketSigner>,
+    user: AuthenticatedUser,
+    request: HttpRequest,
+) -> actix_web::Result<web::Json<ticket::ConnectionTicket>, auth::AuthError> {
+    let session_generation = auth::session_generation(&request, user.user_id).await?;
+
+    Ok(web::Json(signer.issue(
+        user.user_id,
+        session_generation,
+        Utc::now(),
+    )))
 }
 
+/// The user a websocket upgrade is for, from its ticket if it has one, otherwise from the
+/// session cookie or bearer token.
+async fn websocket_user_id(
+    request: &HttpRequest,
+    signer: &TicketSigner,
+) -> Result<i32, WebsocketAuthError> {
+    let ticket = web::Query::<TicketParams>::from_query(request.query_string())
+        .ok()
+        .and_then(|params| params.into_inner().ticket);
+    if let Some(ticket) = &ticket {
+        let claims = signer
+            .verify(ticket, Utc::now())
+            .context(InvalidTicketSnafu)?;
+        let session_generation = auth::session_generation(request, claims.user_id).await?;
+        claims
+            .ensure_current(session_generation)
+            .context(InvalidTicketSnafu)?;
+
+        return Ok(claims.user_id);
+    }
+
+    let user = AuthenticatedUser::extract(request).await?;
+    Ok(user.user_id)
+}
+
+// each extractor is its own argument, that's how actix hands them over
+#[allow(clippy::too_many_arguments)]
 pub async fn websocket_connection(
     db_pool: web::Data<DbPool>,
-    // session: Session,
+    registry: web::Data<SocketRegistry>,
+    heartbeat_config: web::Data<HeartbeatConfig>,
+    rate_limit_config: web::Data<RateLimitConfig>,
+    signer: web::Data<TicketSigner>,
+    upcasters: web::Data<Upcasters>,
+    server_clock: web::Data<ServerClock>,
     request: HttpRequest,
     stream: web::Payload,
 ) -> actix_web::Result<HttpResponse, actix_web::Error> {
-    // let user_id = match session.get::<i32>("user_id") {
-    //     Ok(Some(user_id)) => user_id,
-    //     Ok(None) => todo!("Redirect user to login"),
-    //     Err(err) => todo!("Handle session error: {}", err),
-    // };
-    // bruno can't work with a session and websocket so hardcoding while manually testing
-    let user_id = 1i32;
-
-    let (res, mut ws_session, stream) = actix_ws::handle(&request, stream).unwrap();
+    // checked before the upgrade so a client without credentials gets a plain 401, and
+    // before the query so it can't find out which schema versions are accepted
+    let user_id = websocket_user_id(&request, &signer).await?;
+    auth::ensure_may_sync(&request, user_id).await?;
+    let params = web::Query::<SocketParams>::from_query(request.query_string())?.into_inner();
+
+    let (res, mut ws_session, stream) = actix_ws::handle(&request, stream)?;
+    let mut socket = Socket {
+        session: ws_session.clone(),
+        schema_version: params.schema_version,
+        encoding: params.encoding,
+    };
+    let connection_id = registry.register(user_id, socket.clone()).await;
 
     let mut stream = stream
         .aggregate_continuations()
         // aggregate continuation frames up to 1MiB
         .max_continuation_size(2_usize.pow(20));
 
+    // frames are applied one at a time, in order, by their own task so the receive loop keeps
+    // answering pings and turning away floods while the database is busy
+    let (mut throttle, mut in_flight) = rate_limit::Throttle::new(&rate_limit_config);
+    let mut worker_socket = socket.clone();
+    let worker_registry = registry.clone();
+    actix_web::rt::spawn(async move {
+        while let Some(message) = in_flight.recv().await {
+            let reply =
+                apply_client_message(&db_pool, &worker_registry, user_id, connection_id, message)
+                    .await;
+            if worker_socket.send(&reply).await.is_err() {
+                break;
+            }
+        }
+    });
+
     // start task but don't wait for it
     actix_web::rt::spawn(async move {
-        // receive messages from websocket
-        while let Some(msg) = stream.next().await {
+        let mut heartbeat = heartbeat::Heartbeat::new(&heartbeat_config);
+        let mut pings = actix_web::rt::time::interval_at(
+            actix_web::rt::time::Instant::now() + heartbeat_config.interval,
+            heartbeat_config.interval,
+        );
+
+        // every way out of the loop falls through to the cleanup below
+        let close_reason = loop {
+            let msg = match select(stream.next(), std::pin::pin!(pings.tick())).await {
+                Either::Left((Some(msg), _)) => msg,
+                // the client went away without a close frame
+                Either::Left((None, _)) => break None,
+                Either::Right(_) => {
+                    if !heartbeat.ping() {
+                        break Some(CloseReason {
+                            code: CloseCode::Away,
+                            description: Some("Missed heartbeat".to_string()),
+                        });
+                    }
+                    if ws_session.ping(b"").await.is_err() {
+                        break None;
+                    }
+                    continue;
+                }
+            };
+
             match msg {
                 Ok(AggregatedMessage::Text(text)) => {
-                    let mut conn = db_pool.get().await.unwrap();
-                    let mut event_database = event_database::EventDatabase::new(&mut conn);
-
-                    let event = events::deserialize_event(text).unwrap();
-                    events::handle_event(user_id, event, &mut event_database)
-                        .await
-                        .unwrap();
+                    let admission = throttle
+                        .admit(|| events::deserialize_message(text, &upcasters, &server_clock));
+                    if let Err(close_reason) = reply_to_admission(&mut socket, admission).await {
+                        break close_reason;
+                    }
                 }
 
                 Ok(AggregatedMessage::Binary(bin)) => {
-                    // echo binary message
-                    ws_session.binary(bin).await.unwrap();
+                    let admission = throttle.admit(|| {
+                        events::deserialize_cbor_message(&bin, &upcasters, &server_clock)
+                    });
+                    if let Err(close_reason) = reply_to_admission(&mut socket, admission).await {
+                        break close_reason;
+                    }
                 }
 
                 Ok(AggregatedMessage::Ping(msg)) => {
                     // respond to PING frame with PONG frame
-                    ws_session.pong(&msg).await.unwrap();
+                    if ws_session.pong(&msg).await.is_err() {
+                        break None;
+                    }
                 }
 
-                _ => {}
+                Ok(AggregatedMessage::Pong(_)) => heartbeat.pong(),
+
+                // echo the client's close frame back to finish the closing handshake
+                Ok(AggregatedMessage::Close(reason)) => break reason,
+
+                Err(_) => {
+                    break Some(CloseReason {
+                        code: CloseCode::Protocol,
+                        description: None,
+                    });
+                }
             }
-        }
+        };
+
+        registry.unregister(user_id, connection_id).await;
+        // fails if the socket is already gone, which is fine since it's being dropped anyway
+        let _ = ws_session.close(close_reason).await;
     });
     Ok(res)
 }
 
-async fn get_user(
-    user_email: &Email,
-    conn: &mut pg::AsyncPgConnection,
-) -> Result<User, diesel::result::Error> {
-    use app_db::schema::users::dsl::*;
+/// Sends whatever a frame that wasn't queued gets back. Errors with the close reason when the
+/// connection should be dropped.
+async fn reply_to_admission(
+    socket: &mut Socket,
+    admission: rate_limit::Admission,
+) -> Result<(), Option<CloseReason>> {
+    match admission {
+        rate_limit::Admission::Queued => Ok(()),
+        rate_limit::Admission::Reply(reply) => socket.send(&reply).await.map_err(|_| None),
+        rate_limit::Admission::Disconnect => Err(Some(CloseReason {
+            code: CloseCode::Policy,
+            description: Some("Too many messages".to_string()),
+        })),
+    }
+}
+
+/// Applies a single event or a batch from a client, returning the reply for it.
+async fn apply_client_message(
+    db_pool: &DbPool,
+    registry: &SocketRegistry,
+    user_id: i32,
+    connection_id: connections::ConnectionId,
+    message: events::ClientMessage,
+) -> events::ServerMessage {
+    match message {
+        events::ClientMessage::Event(event) => {
+            apply_event_message(db_pool, registry, user_id, connection_id, event).await
+        }
+        events::ClientMessage::Batch { batch_id, events } => {
+            apply_batch_message(db_pool, registry, user_id, connection_id, batch_id, events).await
+        }
+    }
+}
+
+/// Checks out a connection for applying a client's events. The client is only told to try
+/// again later, so why the pool couldn't hand one out is logged here.
+async fn checkout_event_connection(db_pool: &DbPool) -> Option<Object<AsyncPgConnection>> {
+    db_pool
+        .get()
+        .await
+        .inspect_err(|err| log::error!("Error getting a database connection: {err}"))
+        .ok()
+}
+
+/// Applies an event from a client and forwards it to the user's other devices once it's
+/// committed, returning the ack or nack to send back to the client.
+async fn apply_event_message(
+    db_pool: &DbPool,
+    registry: &SocketRegistry,
+    user_id: i32,
+    connection_id: connections::ConnectionId,
+    event: events::Event,
+) -> events::ServerMessage {
+    let event_id = event.id();
+    let Some(mut conn) = checkout_event_connection(db_pool).await else {
+        return events::ServerMessage::Nack {
+            event_id: Some(event_id),
+            code: events::RejectionCode::InternalError,
+            reason: "Internal server error. Please try again later.".to_string(),
+        };
+    };
+    let mut event_database = event_database::EventDatabase::new(&mut conn);
+
+    match events::handle_event(user_id, event.clone(), &mut event_database).await {
+        Ok(events::EventOutcome::Applied { sequence }) => {
+            let schema_version = event.schema_version();
+            let message = events::ServerMessage::Event { sequence, event };
+            forward_to_peers(registry, user_id, connection_id, schema_version, &message).await;
+
+            events::ServerMessage::Ack {
+                event_id,
+                sequence,
+                superseded: false,
+            }
+        }
+        // peers already received the event the first time it was applied
+        Ok(events::EventOutcome::Replayed { sequence }) => events::ServerMessage::Ack {
+            event_id,
+            sequence,
+            superseded: false,
+        },
+        // peers already have the change that won
+        Ok(events::EventOutcome::Superseded { sequence }) => events::ServerMessage::Ack {
+            event_id,
+            sequence,
+            superseded: true,
+        },
+        Err(err) => err.nack(event_id),
+    }
+}
+
+/// Applies a batch of events in one transaction and forwards the newly applied ones to the
+/// user's other devices once it's committed, returning one ack or nack for the whole batch.
+async fn apply_batch_message(
+    db_pool: &DbPool,
+    registry: &SocketRegistry,
+    user_id: i32,
+    connection_id: connections::ConnectionId,
+    batch_id: uuid::Uuid,
+    events: Vec<events::Event>,
+) -> events::ServerMessage {
+    let Some(mut conn) = checkout_event_connection(db_pool).await else {
+        return events::ServerMessage::BatchNack {
+            batch_id,
+            event_id: None,
+            code: events::RejectionCode::InternalError,
+            reason: "Internal server error. Please try again later.".to_string(),
+        };
+    };
+    let mut event_database = event_database::EventDatabase::new(&mut conn);
+
+    match events::handle_batch(user_id, events.clone(), &mut event_database).await {
+        Ok(outcomes) => {
+            let mut acks = Vec::with_capacity(events.len());
+            for (event, outcome) in events.into_iter().zip(outcomes) {
+                let event_id = event.id();
+                let (sequence, superseded) = match outcome {
+                    events::EventOutcome::Applied { sequence } => {
+                        let schema_version = event.schema_version();
+                        let message = events::ServerMessage::Event { sequence, event };
+                        forward_to_peers(
+                            registry,
+                            user_id,
+                            connection_id,
+                            schema_version,
+                            &message,
+                        )
+                        .await;
+                        (sequence, false)
+                    }
+                    // peers already received the event the first time it was applied
+                    events::EventOutcome::Replayed { sequence } => (sequence, false),
+                    // peers already have the change that won
+                    events::EventOutcome::Superseded { sequence } => (sequence, true),
+                };
+                acks.push(events::EventAck {
+                    event_id,
+                    sequence,
+                    superseded,
+                });
+            }
 
-    let user_id: User = users
-        .filter(email.eq(&user_email.as_str()))
-        .select(User::as_select())
-        .first(conn)
-        .await?;
+            events::ServerMessage::BatchAck { batch_id, acks }
+        }
+        Err(err) => err.nack(batch_id),
+    }
+}
 
-    Ok(user_id)
+/// Sends a committed event to the user's other open sockets, dropping any that have closed.
+/// Devices on an older schema than the event are skipped since they can't apply it.
+async fn forward_to_peers(
+    registry: &SocketRegistry,
+    user_id: i32,
+    sender: connections::ConnectionId,
+    schema_version: i32,
+    message: &events::ServerMessage,
+) {
+    for (connection_id, mut peer) in registry.peers(user_id, sender).await {
+        if peer.schema_version < schema_version {
+            continue;
+        }
+
+        if peer.send(message).await.is_err() {
+            registry.unregister(user_id, connection_id).await;
+        }
+    }
 }
 
 #[cfg(test)]
@@ -357,9 +1262,27 @@ mod tests {
     use super::*;
     use crate::db::Database;
 
+    type GetUserFn = Box<dyn Fn(&Email) -> Result<User, diesel::result::Error> + Send + Sync>;
+    type CreateUserFn = Box<dyn Fn(UserInput) -> Result<i32, diesel::result::Error> + Send + Sync>;
+    type CreateApiTokenFn =
+        Box<dyn Fn(NewApiToken) -> Result<(), diesel::result::Error> + Send + Sync>;
+    type CreatePasswordResetFn =
+        Box<dyn Fn(NewPasswordReset) -> Result<(), diesel::result::Error> + Send + Sync>;
+    type ResetPasswordFn = Box<
+        dyn Fn(&str, DateTime<Utc>) -> Result<Option<i32>, diesel::result::Error> + Send + Sync,
+    >;
+    type CreateEmailVerificationFn =
+        Box<dyn Fn(NewEmailVerification) -> Result<(), diesel::result::Error> + Send + Sync>;
+    type VerifyEmailFn = ResetPasswordFn;
+
     pub struct MockDatabase {
-        get_user_fn: Box<dyn Fn(&Email) -> Result<User, diesel::result::Error> + Send + Sync>,
-        create_user_fn: Box<dyn Fn(UserInput) -> Result<(), diesel::result::Error> + Send + Sync>,
+        get_user_fn: GetUserFn,
+        create_user_fn: CreateUserFn,
+        create_api_token_fn: CreateApiTokenFn,
+        create_password_reset_fn: CreatePasswordResetFn,
+        reset_password_fn: ResetPasswordFn,
+        create_email_verification_fn: CreateEmailVerificationFn,
+        verify_email_fn: VerifyEmailFn,
     }
 
     impl MockDatabase {
@@ -368,20 +1291,15 @@ mod tests {
         }
     }
 
+    #[derive(Default)]
     pub struct MockDatabaseBuilder {
-        get_user_fn:
-            Option<Box<dyn Fn(&Email) -> Result<User, diesel::result::Error> + Send + Sync>>,
-        create_user_fn:
-            Option<Box<dyn Fn(UserInput) -> Result<(), diesel::result::Error> + Send + Sync>>,
-    }
-
-    impl Default for MockDatabaseBuilder {
-        fn default() -> Self {
-            Self {
-                get_user_fn: None,
-                create_user_fn: None,
-            }
-        }
+        get_user_fn: Option<GetUserFn>,
+        create_user_fn: Option<CreateUserFn>,
+        create_api_token_fn: Option<CreateApiTokenFn>,
+        create_password_reset_fn: Option<CreatePasswordResetFn>,
+        reset_password_fn: Option<ResetPasswordFn>,
+        create_email_verification_fn: Option<CreateEmailVerificationFn>,
+        verify_email_fn: Option<VerifyEmailFn>,
     }
 
     impl MockDatabaseBuilder {
@@ -395,18 +1313,82 @@ mod tests {
 
         pub fn with_create_user<F>(mut self, f: F) -> Self
         where
-            F: Fn(UserInput) -> Result<(), diesel::result::Error> + Send + Sync + 'static,
+            F: Fn(UserInput) -> Result<i32, diesel::result::Error> + Send + Sync + 'static,
         {
             self.create_user_fn = Some(Box::new(f));
             self
         }
 
+        pub fn with_create_api_token<F>(mut self, f: F) -> Self
+        where
+            F: Fn(NewApiToken) -> Result<(), diesel::result::Error> + Send + Sync + 'static,
+        {
+            self.create_api_token_fn = Some(Box::new(f));
+            self
+        }
+
+        pub fn with_create_password_reset<F>(mut self, f: F) -> Self
+        where
+            F: Fn(NewPasswordReset) -> Result<(), diesel::result::Error> + Send + Sync + 'static,
+        {
+            self.create_password_reset_fn = Some(Box::new(f));
+            self
+        }
+
+        pub fn with_reset_password<F>(mut self, f: F) -> Self
+        where
+            F: Fn(&str, DateTime<Utc>) -> Result<Option<i32>, diesel::result::Error>
+                + Send
+                + Sync
+                + 'static,
+        {
+            self.reset_password_fn = Some(Box::new(f));
+            self
+        }
+
+        pub fn with_create_email_verification<F>(mut self, f: F) -> Self
+        where
+            F: Fn(NewEmailVerification) -> Result<(), diesel::result::Error>
+                + Send
+                + Sync
+                + 'static,
+        {
+            self.create_email_verification_fn = Some(Box::new(f));
+            self
+        }
+
+        pub fn with_verify_email<F>(mut self, f: F) -> Self
+        where
+            F: Fn(&str, DateTime<Utc>) -> Result<Option<i32>, diesel::result::Error>
+                + Send
+                + Sync
+                + 'static,
+        {
+            self.verify_email_fn = Some(Box::new(f));
+            self
+        }
+
         pub fn build(self) -> MockDatabase {
             MockDatabase {
                 get_user_fn: self
                     .get_user_fn
                     .unwrap_or_else(|| Box::new(|_| Err(diesel::result::Error::NotFound))),
-                create_user_fn: self.create_user_fn.unwrap_or_else(|| Box::new(|_| Ok(()))),
+                create_user_fn: self.create_user_fn.unwrap_or_else(|| Box::new(|_| Ok(1))),
+                create_api_token_fn: self
+                    .create_api_token_fn
+                    .unwrap_or_else(|| Box::new(|_| Ok(()))),
+                create_password_reset_fn: self
+                    .create_password_reset_fn
+                    .unwrap_or_else(|| Box::new(|_| Ok(()))),
+                reset_password_fn: self
+                    .reset_password_fn
+                    .unwrap_or_else(|| Box::new(|_, _| Ok(None))),
+                create_email_verification_fn: self
+                    .create_email_verification_fn
+                    .unwrap_or_else(|| Box::new(|_| Ok(()))),
+                verify_email_fn: self
+                    .verify_email_fn
+                    .unwrap_or_else(|| Box::new(|_, _| Ok(None))),
             }
         }
     }
@@ -416,24 +1398,431 @@ mod tests {
             (self.get_user_fn)(user_email)
         }
 
-        async fn create_user(&mut self, user: UserInput) -> Result<(), diesel::result::Error> {
+        async fn get_user_by_id(&mut self, _user_id: i32) -> Result<User, diesel::result::Error> {
+            Err(diesel::result::Error::NotFound)
+        }
+
+        async fn create_user(&mut self, user: UserInput) -> Result<i32, diesel::result::Error> {
             (self.create_user_fn)(user)
         }
+
+        async fn create_api_token(
+            &mut self,
+            api_token: NewApiToken,
+        ) -> Result<(), diesel::result::Error> {
+            (self.create_api_token_fn)(api_token)
+        }
+
+        async fn delete_api_tokens(
+            &mut self,
+            _user_id: i32,
+        ) -> Result<usize, diesel::result::Error> {
+            Ok(0)
+        }
+
+        async fn revoke_connection_tickets(
+            &mut self,
+            _user_id: i32,
+        ) -> Result<(), diesel::result::Error> {
+            Ok(())
+        }
+
+        async fn create_password_reset(
+            &mut self,
+            reset: NewPasswordReset,
+        ) -> Result<(), diesel::result::Error> {
+            (self.create_password_reset_fn)(reset)
+        }
+
+        async fn reset_password(
+            &mut self,
+            token_hash: &str,
+            _password_hash: String,
+            now: DateTime<Utc>,
+        ) -> Result<Option<i32>, diesel::result::Error> {
+            (self.reset_password_fn)(token_hash, now)
+        }
+
+        async fn create_email_verification(
+            &mut self,
+            verification: NewEmailVerification,
+        ) -> Result<(), diesel::result::Error> {
+            (self.create_email_verification_fn)(verification)
+        }
+
+        async fn verify_email(
+            &mut self,
+            token_hash: &str,
+            now: DateTime<Utc>,
+        ) -> Result<Option<i32>, diesel::result::Error> {
+            (self.verify_email_fn)(token_hash, now)
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingMailer {
+        sent: std::sync::Mutex<Vec<OutgoingMail>>,
+    }
+
+    impl Mailer for RecordingMailer {
+        fn send(
+            &self,
+            mail: OutgoingMail,
+        ) -> futures_util::future::BoxFuture<'_, Result<(), MailError>> {
+            self.sent.lock().unwrap().push(mail);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    /// Picks the token out of an email by the hash stored for it, so the tests don't depend on
+    /// where in the wording it appears.
+    fn mailed_token(body: &str, token_hash: &str) -> Option<String> {
+        body.split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .find(|word| auth_utils::hash_token(word) == token_hash)
+            .map(str::to_string)
     }
 
     #[actix_web::test]
     async fn test_create_user() {
         let mock_db = MockDatabase::builder()
-            .with_create_user(|_user| Ok(()))
+            .with_create_user(|_user| Ok(1))
+            .build();
+        let mailer = RecordingMailer::default();
+
+        let credentials = SignupCredentials {
+            email: "test@example.com".to_string(),
+            password: "password123".to_string(),
+        };
+
+        let result = create_user_from_signup(
+            credentials,
+            mock_db,
+            &PasswordPolicy::default(),
+            &mailer,
+            Utc::now(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn test_signup_rejects_common_password() {
+        let mock_db = MockDatabase::builder()
+            .with_create_user(|_user| panic!("user created with a common password"))
             .build();
+        let mailer = RecordingMailer::default();
+        let policy = PasswordPolicy {
+            common_passwords: std::collections::HashSet::from(["password123".to_string()]),
+            ..PasswordPolicy::default()
+        };
 
         let credentials = SignupCredentials {
             email: "test@example.com".to_string(),
+            password: "Password123".to_string(),
+        };
+
+        let result =
+            create_user_from_signup(credentials, mock_db, &policy, &mailer, Utc::now()).await;
+        assert!(matches!(result, Err(SignupError::CommonPassword)));
+    }
+
+    #[actix_web::test]
+    async fn test_signup_mails_verification_token() {
+        let stored_hash = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+        let stored = stored_hash.clone();
+        let mock_db = MockDatabase::builder()
+            .with_create_user(|_user| Ok(7))
+            .with_create_email_verification(move |verification| {
+                assert_eq!(verification.user_id, 7);
+                *stored.lock().unwrap() = verification.token_hash;
+                Ok(())
+            })
+            .build();
+        let mailer = RecordingMailer::default();
+
+        let credentials = SignupCredentials {
+            email: "test@example.com".to_string(),
+            password: "password123".to_string(),
+        };
+        create_user_from_signup(
+            credentials,
+            mock_db,
+            &PasswordPolicy::default(),
+            &mailer,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        let sent = mailer.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "test@example.com");
+        assert!(mailed_token(&sent[0].body, &stored_hash.lock().unwrap()).is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_signup_points_to_resend_when_verification_email_fails() {
+        let mock_db = MockDatabase::builder()
+            .with_create_user(|_user| Ok(7))
+            .with_create_email_verification(|_verification| {
+                Err(diesel::result::Error::BrokenTransactionManager)
+            })
+            .build();
+        let mailer = RecordingMailer::default();
+
+        let credentials = SignupCredentials {
+            email: "test@example.com".to_string(),
+            password: "password123".to_string(),
+        };
+        let response = create_user_from_signup(
+            credentials,
+            mock_db,
+            &PasswordPolicy::default(),
+            &mailer,
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!response.verification_email_sent);
+        assert_eq!(response.resend_path, Some("/verify-email/resend"));
+        assert!(mailer.sent.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_verify_email_with_unknown_token() {
+        let mut mock_db = MockDatabase::builder()
+            .with_verify_email(|_token_hash, _now| Ok(None))
+            .build();
+
+        let confirmation = EmailVerificationConfirmation {
+            token: "unknown".to_string(),
+        };
+        let result = confirm_email(confirmation, &mut mock_db, Utc::now()).await;
+
+        assert!(matches!(
+            result,
+            Err(VerificationError::InvalidVerificationToken)
+        ));
+    }
+
+    fn user_with_password(password: &str) -> User {
+        let now = Utc::now();
+        User {
+            id: 1,
+            email: Email::new("test@example.com").unwrap(),
+            password_hash: auth_utils::generate_password_hash(password).unwrap(),
+            created_at: now,
+            verified_at: Some(now),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_user() {
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| Ok(user_with_password("password123")))
+            .build();
+
+        let credentials = UserLogin {
+            email: Email::new("test@example.com").unwrap(),
+            password: "password123".to_string(),
+        };
+
+        let user = authenticate_user(
+            credentials,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            &VerificationPolicy::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(user.id, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_user_wrong_password() {
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| Ok(user_with_password("password123")))
+            .build();
+
+        let credentials = UserLogin {
+            email: Email::new("test@example.com").unwrap(),
+            password: "wrong".to_string(),
+        };
+
+        let result = authenticate_user(
+            credentials,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            &VerificationPolicy::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(LoginError::InvalidCredentials)));
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_user_with_overlong_password() {
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| panic!("looked up a user for an overlong password"))
+            .build();
+
+        let credentials = UserLogin {
+            email: Email::new("test@example.com").unwrap(),
+            password: "a".repeat(PasswordPolicy::default().max_length + 1),
+        };
+
+        let result = authenticate_user(
+            credentials,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            &VerificationPolicy::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(LoginError::InvalidCredentials)));
+    }
+
+    #[actix_web::test]
+    async fn test_authenticate_unverified_user_past_login_grace_period() {
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| {
+                let mut user = user_with_password("password123");
+                user.created_at -= chrono::Duration::days(10);
+                user.verified_at = None;
+                Ok(user)
+            })
+            .build();
+        let policy = VerificationPolicy {
+            login_grace_period: Some(chrono::Duration::days(3)),
+            ..VerificationPolicy::default()
+        };
+
+        let credentials = UserLogin {
+            email: Email::new("test@example.com").unwrap(),
             password: "password123".to_string(),
         };
 
-        let result = create_user_from_signup(credentials, mock_db).await;
+        let result = authenticate_user(
+            credentials,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            &policy,
+        )
+        .await;
+        assert!(matches!(result, Err(LoginError::Unverified)));
+    }
+
+    #[actix_web::test]
+    async fn test_issue_api_token_stores_hash() {
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| Ok(user_with_password("password123")))
+            .with_create_api_token(|api_token| {
+                assert_eq!(api_token.user_id, 1);
+                assert_eq!(api_token.name, "iPhone");
+                assert_eq!(api_token.token_hash.len(), 64);
+                Ok(())
+            })
+            .build();
+
+        let request = ApiTokenRequest {
+            email: Email::new("test@example.com").unwrap(),
+            password: "password123".to_string(),
+            name: " iPhone ".to_string(),
+        };
+
+        let issued = issue_api_token(
+            request,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            &VerificationPolicy::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(issued.name, "iPhone");
+        assert_ne!(issued.token, auth_utils::hash_token(&issued.token));
+    }
+
+    #[actix_web::test]
+    async fn test_issue_api_token_wrong_password() {
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| Ok(user_with_password("password123")))
+            .with_create_api_token(|_api_token| panic!("token issued for a wrong password"))
+            .build();
+
+        let request = ApiTokenRequest {
+            email: Email::new("test@example.com").unwrap(),
+            password: "wrong".to_string(),
+            name: "iPhone".to_string(),
+        };
+
+        let result = issue_api_token(
+            request,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            &VerificationPolicy::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(ApiTokenError::Login { .. })));
+    }
+
+    #[actix_web::test]
+    async fn test_password_reset_mails_token() {
+        let stored_hash = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+        let stored = stored_hash.clone();
+        let mut mock_db = MockDatabase::builder()
+            .with_get_user(|_email| Ok(user_with_password("password123")))
+            .with_create_password_reset(move |reset| {
+                *stored.lock().unwrap() = reset.token_hash;
+                Ok(())
+            })
+            .build();
+        let mailer = RecordingMailer::default();
+
+        let request = PasswordResetRequest {
+            email: Email::new("test@example.com").unwrap(),
+        };
+        start_password_reset(request, &mut mock_db, &mailer, Utc::now())
+            .await
+            .unwrap();
+
+        let sent = mailer.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "test@example.com");
+        assert!(mailed_token(&sent[0].body, &stored_hash.lock().unwrap()).is_some());
+    }
+
+    #[actix_web::test]
+    async fn test_password_reset_unknown_email_sends_nothing() {
+        let mut mock_db = MockDatabase::builder().build();
+        let mailer = RecordingMailer::default();
+
+        let request = PasswordResetRequest {
+            email: Email::new("nobody@example.com").unwrap(),
+        };
+        let result = start_password_reset(request, &mut mock_db, &mailer, Utc::now()).await;
+
         assert!(result.is_ok());
+        assert!(mailer.sent.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_confirm_password_reset_with_used_token() {
+        let mut mock_db = MockDatabase::builder()
+            .with_reset_password(|_token_hash, _now| Ok(None))
+            .build();
+
+        let confirmation = PasswordResetConfirmation {
+            token: "already used".to_string(),
+            password: "new password".to_string(),
+        };
+        let result = finish_password_reset(
+            confirmation,
+            &mut mock_db,
+            &PasswordPolicy::default(),
+            Utc::now(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(PasswordResetError::InvalidResetToken)));
     }
 
     // #[actix_rt::test]
@@ -0,0 +1,163 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use futures_util::future::BoxFuture;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use snafu::prelude::*;
+
+/// A plain text email to a single recipient.
+#[derive(Debug, Clone)]
+pub struct OutgoingMail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug, Snafu)]
+pub enum MailError {
+    #[snafu(display("Invalid address {address}: {source}"))]
+    InvalidAddress {
+        address: String,
+        source: lettre::address::AddressError,
+    },
+    #[snafu(display("Failed to build the email: {source}"))]
+    Build { source: lettre::error::Error },
+    #[snafu(display("Failed to write the email: {source}"))]
+    Write { source: std::io::Error },
+    #[snafu(display("Failed to send the email: {source}"))]
+    Smtp {
+        source: lettre::transport::smtp::Error,
+    },
+}
+
+/// Delivers the emails the server sends, like password resets. Picked once at startup and
+/// shared as `web::Data<dyn Mailer>`, so it's object safe.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: OutgoingMail) -> BoxFuture<'_, Result<(), MailError>>;
+}
+
+/// Writes emails to stdout or appends them to a file instead of sending them, for local
+/// development and tests.
+pub struct FileMailer {
+    path: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn stdout() -> Self {
+        Self { path: None }
+    }
+
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    fn write(&self, mail: &OutgoingMail) -> std::io::Result<()> {
+        let message = format!(
+            "To: {}\nSubject: {}\n\n{}\n\n",
+            mail.to, mail.subject, mail.body
+        );
+
+        match &self.path {
+            Some(path) => std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(message.as_bytes()),
+            None => std::io::stdout().lock().write_all(message.as_bytes()),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: OutgoingMail) -> BoxFuture<'_, Result<(), MailError>> {
+        Box::pin(async move { self.write(&mail).context(WriteSnafu) })
+    }
+}
+
+/// Where and how to reach an SMTP server.
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Upgrade the connection with STARTTLS. Only worth turning off for a local stand-in
+    /// server, everything including the login is sent in the clear without it.
+    pub starttls: bool,
+    pub from: String,
+}
+
+/// Sends emails through an SMTP server.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: SmtpConfig) -> Result<Self, MailError> {
+        let from = config.from.parse().context(InvalidAddressSnafu {
+            address: config.from.clone(),
+        })?;
+
+        let mut transport = if config.starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host).context(SmtpSnafu)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+        }
+        .port(config.port);
+        if let (Some(username), Some(password)) = (config.username, config.password) {
+            transport = transport.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            from,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: OutgoingMail) -> BoxFuture<'_, Result<(), MailError>> {
+        Box::pin(async move {
+            let to = mail.to.parse().context(InvalidAddressSnafu {
+                address: mail.to.clone(),
+            })?;
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(mail.subject)
+                .body(mail.body)
+                .context(BuildSnafu)?;
+
+            self.transport.send(message).await.context(SmtpSnafu)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn file_mailer_appends() {
+        let path = std::env::temp_dir().join(format!("mailer-{}.txt", uuid::Uuid::new_v4()));
+        let mailer = FileMailer::file(&path);
+        let mail = |subject: &str| OutgoingMail {
+            to: "test@example.com".to_string(),
+            subject: subject.to_string(),
+            body: "body".to_string(),
+        };
+
+        mailer.send(mail("first")).await.unwrap();
+        mailer.send(mail("second")).await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.contains("Subject: first"));
+        assert!(written.contains("Subject: second"));
+    }
+}
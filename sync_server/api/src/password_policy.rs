@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use snafu::prelude::*;
+
+use crate::{CommonPasswordSnafu, PasswordTooLongSnafu, PasswordTooShortSnafu, SignupError};
+
+/// What a new password has to satisfy, both at signup and when resetting it.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    /// Fewest characters a password may have. Anything below 1 is treated as 1.
+    pub min_length: usize,
+    /// Most characters a password may have. Every password goes through Argon2, so this keeps a
+    /// huge one from tying up a worker.
+    pub max_length: usize,
+    /// Passwords too well known to allow, stored lowercased.
+    pub common_passwords: HashSet<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            common_passwords: HashSet::new(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Whether the password is over the maximum length, checked on its own at login so an
+    /// oversized password is turned away before it's hashed.
+    pub fn is_too_long(&self, password: &str) -> bool {
+        password.chars().count() > self.max_length
+    }
+
+    pub fn check(&self, password: &str) -> Result<(), SignupError> {
+        let length = password.chars().count();
+        let min_length = self.min_length.max(1);
+        ensure!(length >= min_length, PasswordTooShortSnafu { min_length });
+        ensure!(
+            !self.is_too_long(password),
+            PasswordTooLongSnafu {
+                max_length: self.max_length
+            }
+        );
+        ensure!(
+            !self.common_passwords.contains(&password.to_lowercase()),
+            CommonPasswordSnafu
+        );
+
+        Ok(())
+    }
+}
+
+/// Reads a list of common or breached passwords, one per line. Blank lines are skipped.
+pub fn read_common_passwords(path: impl AsRef<Path>) -> std::io::Result<HashSet<String>> {
+    let contents = std::fs::read_to_string(path)?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_lowercase)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 16,
+            common_passwords: HashSet::from(["password1".to_string()]),
+        }
+    }
+
+    #[test]
+    fn accepts_password_within_rules() {
+        assert!(policy().check("correct horse").is_ok());
+    }
+
+    #[test]
+    fn rejects_short_and_long_passwords() {
+        assert!(matches!(
+            policy().check("short"),
+            Err(SignupError::PasswordTooShort { min_length: 8 })
+        ));
+        assert!(matches!(
+            policy().check(&"a".repeat(17)),
+            Err(SignupError::PasswordTooLong { max_length: 16 })
+        ));
+    }
+
+    #[test]
+    fn rejects_common_password_in_any_case() {
+        assert!(matches!(
+            policy().check("PassWord1"),
+            Err(SignupError::CommonPassword)
+        ));
+    }
+
+    #[test]
+    fn reads_common_passwords_file() {
+        let path = std::env::temp_dir().join(format!("passwords-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "123456\n\n  Qwerty  \n").unwrap();
+
+        let passwords = read_common_passwords(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(passwords.len(), 2);
+        assert!(passwords.contains("qwerty"));
+    }
+}
@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::events::{ClientMessage, DeserializeMessageError, ServerMessage};
+
+/// Limits on how fast a single websocket may send frames. Every applied frame holds a
+/// database connection, so an unthrottled client could starve the rest of the server.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    /// Frames a connection earns back per second.
+    pub messages_per_second: u32,
+    /// Frames a connection may send in a burst, e.g. when flushing its outbox on reconnect.
+    pub burst: u32,
+    /// Frames that may be waiting to be applied before new ones are turned away.
+    pub max_in_flight: usize,
+    /// Frames in a row that may be turned away before the connection is dropped.
+    pub max_slow_downs: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 20,
+            burst: 50,
+            max_in_flight: 16,
+            max_slow_downs: 20,
+        }
+    }
+}
+
+/// A token bucket holding up to `burst` frames, refilled at `messages_per_second`.
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    capacity: f64,
+    refill_per_second: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        let capacity = f64::from(config.burst.max(1));
+        Self {
+            tokens: capacity,
+            capacity,
+            refill_per_second: f64::from(config.messages_per_second.max(1)),
+            refilled_at: now,
+        }
+    }
+
+    /// Takes a token for a frame, or returns how long until one is available.
+    pub(crate) fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens < 1.0 {
+            return Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.refill_per_second,
+            ));
+        }
+
+        self.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// How long it takes to earn back a single token.
+    pub(crate) fn refill_interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.refill_per_second)
+    }
+}
+
+/// What to do with a frame a client sent.
+pub(crate) enum Admission {
+    /// The frame was queued to be applied, its ack is sent once it has been.
+    Queued,
+    /// The frame wasn't queued, send this back instead.
+    Reply(ServerMessage),
+    /// The client kept sending after being told to slow down.
+    Disconnect,
+}
+
+/// Decides which of a connection's frames make it into its bounded in-flight queue.
+pub(crate) struct Throttle {
+    bucket: TokenBucket,
+    queue: mpsc::Sender<ClientMessage>,
+    slow_downs: u32,
+    max_slow_downs: u32,
+}
+
+impl Throttle {
+    /// Creates the throttle along with the receiving end of its in-flight queue.
+    pub(crate) fn new(config: &RateLimitConfig) -> (Self, mpsc::Receiver<ClientMessage>) {
+        let (queue, in_flight) = mpsc::channel(config.max_in_flight.max(1));
+        let throttle = Self {
+            bucket: TokenBucket::new(config, Instant::now()),
+            queue,
+            slow_downs: 0,
+            max_slow_downs: config.max_slow_downs,
+        };
+
+        (throttle, in_flight)
+    }
+
+    /// Queues a frame if the connection is within its limits. The frame is only decoded once
+    /// it's been let through, so a flood costs as little as possible.
+    pub(crate) fn admit(
+        &mut self,
+        decode: impl FnOnce() -> Result<ClientMessage, DeserializeMessageError>,
+    ) -> Admission {
+        if let Err(retry_after) = self.bucket.try_take(Instant::now()) {
+            return self.slow_down(retry_after);
+        }
+
+        let message = match decode() {
+            Ok(message) => message,
+            Err(err) => return Admission::Reply(err.nack()),
+        };
+
+        match self.queue.try_send(message) {
+            Ok(()) => {
+                self.slow_downs = 0;
+                Admission::Queued
+            }
+            Err(TrySendError::Full(_)) => self.slow_down(self.bucket.refill_interval()),
+            // the task applying the queue only stops once the socket is gone
+            Err(TrySendError::Closed(_)) => Admission::Disconnect,
+        }
+    }
+
+    fn slow_down(&mut self, retry_after: Duration) -> Admission {
+        self.slow_downs += 1;
+        if self.slow_downs > self.max_slow_downs {
+            return Admission::Disconnect;
+        }
+
+        Admission::Reply(ServerMessage::SlowDown {
+            retry_after_ms: u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytestring::ByteString;
+
+    fn bucket(now: Instant) -> TokenBucket {
+        TokenBucket::new(
+            &RateLimitConfig {
+                messages_per_second: 10,
+                burst: 3,
+                ..RateLimitConfig::default()
+            },
+            now,
+        )
+    }
+
+    #[test]
+    fn allows_burst_then_throttles() {
+        let now = Instant::now();
+        let mut bucket = bucket(now);
+
+        for _ in 0..3 {
+            assert!(bucket.try_take(now).is_ok());
+        }
+        let retry_after = bucket.try_take(now).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let now = Instant::now();
+        let mut bucket = bucket(now);
+        for _ in 0..3 {
+            bucket.try_take(now).unwrap();
+        }
+
+        let later = now + Duration::from_millis(250);
+        assert!(bucket.try_take(later).is_ok());
+        assert!(bucket.try_take(later).is_ok());
+        assert!(bucket.try_take(later).is_err());
+    }
+
+    #[test]
+    fn refill_is_capped_at_burst() {
+        let now = Instant::now();
+        let mut bucket = bucket(now);
+
+        let later = now + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(bucket.try_take(later).is_ok());
+        }
+        assert!(bucket.try_take(later).is_err());
+    }
+
+    const EVENT: &str = r#"{
+        "id": "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21",
+        "schema_version": 1,
+        "data": {"type": "DeleteProject", "project_id": 1}
+    }"#;
+
+    fn decode() -> Result<ClientMessage, DeserializeMessageError> {
+        crate::events::deserialize_message(
+            ByteString::from_static(EVENT),
+            &crate::events::Upcasters::new(),
+            &event_database::ServerClock::new(),
+        )
+    }
+
+    #[test]
+    fn slows_down_when_queue_is_full() {
+        let (mut throttle, _in_flight) = Throttle::new(&RateLimitConfig {
+            max_in_flight: 1,
+            ..RateLimitConfig::default()
+        });
+
+        assert!(matches!(throttle.admit(decode), Admission::Queued));
+        assert!(matches!(
+            throttle.admit(decode),
+            Admission::Reply(ServerMessage::SlowDown { .. })
+        ));
+    }
+
+    #[test]
+    fn disconnects_after_too_many_slow_downs() {
+        let (mut throttle, _in_flight) = Throttle::new(&RateLimitConfig {
+            burst: 1,
+            max_slow_downs: 2,
+            ..RateLimitConfig::default()
+        });
+
+        assert!(matches!(throttle.admit(decode), Admission::Queued));
+        assert!(matches!(throttle.admit(decode), Admission::Reply(_)));
+        assert!(matches!(throttle.admit(decode), Admission::Reply(_)));
+        assert!(matches!(throttle.admit(decode), Admission::Disconnect));
+    }
+}
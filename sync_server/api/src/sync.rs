@@ -0,0 +1,744 @@
+use std::fmt;
+use std::str::FromStr;
+
+use actix_web::http::{StatusCode, header};
+use actix_web::{HttpResponse, HttpResponseBuilder, ResponseError, get, mime, web};
+use chrono::{DateTime, Utc};
+use diesel::{
+    ExpressionMethods, QueryDsl, Queryable, QueryableByName, Selectable, SelectableHelper,
+};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+use snafu::{Location, ResultExt, prelude::*};
+
+use crate::{DbPool, SyncingUser};
+
+/// Rows returned per page when the client doesn't ask for fewer.
+const MAX_PAGE_SIZE: i64 = 500;
+
+/// Tables a client can lazily sync, named as they are in the url.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SyncTable {
+    Projects,
+    Sections,
+    SectionItems,
+    SectionItemNotes,
+}
+
+impl SyncTable {
+    /// The order a snapshot exports tables in, parents before children.
+    const ALL: [SyncTable; 4] = [
+        SyncTable::Projects,
+        SyncTable::Sections,
+        SyncTable::SectionItems,
+        SyncTable::SectionItemNotes,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SyncTable::Projects => "projects",
+            SyncTable::Sections => "sections",
+            SyncTable::SectionItems => "section_items",
+            SyncTable::SectionItemNotes => "section_item_notes",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|table| table.name() == name)
+    }
+
+    fn next(self) -> Option<Self> {
+        let index = Self::ALL.iter().position(|table| *table == self)?;
+        Self::ALL.get(index + 1).copied()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncParams {
+    /// The `next_cursor` of the last page the client stored, or nothing for a first sync.
+    #[serde(default)]
+    since: i64,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotParams {
+    /// The `resume_token` of the last page the client stored, or nothing to start a snapshot.
+    token: Option<SnapshotToken>,
+    limit: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = app_db::schema::projects)]
+struct ProjectRow {
+    #[serde(skip)]
+    id: i32,
+    project_id: i32,
+    title: String,
+    completed: bool,
+    is_deleted: bool,
+    deleted_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    sync_cursor: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = app_db::schema::sections)]
+struct SectionRow {
+    #[serde(skip)]
+    id: i32,
+    project_id: i32,
+    section_id: i32,
+    name: String,
+    is_deleted: bool,
+    deleted_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    sync_cursor: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = app_db::schema::section_items)]
+struct SectionItemRow {
+    #[serde(skip)]
+    id: i32,
+    section_id: i32,
+    item_id: i32,
+    text: String,
+    is_complete: bool,
+    item_order: i32,
+    is_deleted: bool,
+    deleted_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    sync_cursor: i64,
+}
+
+#[derive(Queryable, Selectable, Serialize, Debug)]
+#[diesel(table_name = app_db::schema::section_item_notes)]
+struct SectionItemNoteRow {
+    #[serde(skip)]
+    id: i32,
+    item_id: i32,
+    note_id: i32,
+    text: String,
+    is_deleted: bool,
+    deleted_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    sync_cursor: i64,
+}
+
+trait SyncRow {
+    /// The server's primary key, which snapshots page by.
+    fn id(&self) -> i32;
+
+    /// When the row last changed, which lazy syncs page by.
+    fn cursor(&self) -> i64;
+}
+
+impl SyncRow for ProjectRow {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn cursor(&self) -> i64 {
+        self.sync_cursor
+    }
+}
+
+impl SyncRow for SectionRow {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn cursor(&self) -> i64 {
+        self.sync_cursor
+    }
+}
+
+impl SyncRow for SectionItemRow {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn cursor(&self) -> i64 {
+        self.sync_cursor
+    }
+}
+
+impl SyncRow for SectionItemNoteRow {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn cursor(&self) -> i64 {
+        self.sync_cursor
+    }
+}
+
+/// Which of a table's rows to load.
+#[derive(Debug, Clone, Copy)]
+enum RowRange {
+    /// Rows changed after the cursor, in the order they changed.
+    ChangedSince(i64),
+    /// Rows unchanged since the snapshot started, in primary key order after `after_id`.
+    Snapshot { sync_cursor: i64, after_id: i32 },
+}
+
+/// Rows changed after the requested cursor. Deleted rows are included with `is_deleted` set
+/// so the client can remove them.
+#[derive(Serialize, Debug)]
+struct SyncPage<T> {
+    rows: Vec<T>,
+    /// Pass back as `since` to get the following page, or to catch up later.
+    next_cursor: i64,
+    has_more: bool,
+}
+
+impl<T: SyncRow> SyncPage<T> {
+    /// Builds a page from rows fetched with one more than `page_size`, the extra row only
+    /// telling whether another page follows.
+    fn new(mut rows: Vec<T>, since: i64, page_size: i64) -> Self {
+        let has_more = rows.len() as i64 > page_size;
+        rows.truncate(page_size as usize);
+        let next_cursor = rows.last().map_or(since, SyncRow::cursor);
+
+        Self {
+            rows,
+            next_cursor,
+            has_more,
+        }
+    }
+}
+
+/// Where a snapshot export is up to. Clients treat it as opaque and pass it back unchanged to
+/// continue, including after the app was killed mid-export.
+#[derive(Debug, Clone, PartialEq)]
+struct SnapshotToken {
+    sync_cursor: i64,
+    event_sequence: i64,
+    table: SyncTable,
+    after_id: i32,
+}
+
+impl fmt::Display for SnapshotToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.sync_cursor,
+            self.event_sequence,
+            self.table.name(),
+            self.after_id
+        )
+    }
+}
+
+impl FromStr for SnapshotToken {
+    type Err = SyncError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = token.split('.').collect();
+        let [sync_cursor, event_sequence, table, after_id] = parts[..] else {
+            return InvalidTokenSnafu.fail();
+        };
+
+        Ok(SnapshotToken {
+            sync_cursor: sync_cursor.parse().ok().context(InvalidTokenSnafu)?,
+            event_sequence: event_sequence.parse().ok().context(InvalidTokenSnafu)?,
+            table: SyncTable::from_name(table).context(InvalidTokenSnafu)?,
+            after_id: after_id.parse().ok().context(InvalidTokenSnafu)?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for SnapshotToken {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let token = String::deserialize(deserializer)?;
+        token.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// One table's rows from a snapshot. Once `resume_token` is empty the export is complete and
+/// the client lazily syncs every table from `sync_cursor` to pick up changes made meanwhile,
+/// and resumes the websocket stream after `event_sequence`.
+#[derive(Serialize, Debug)]
+struct SnapshotPage<T> {
+    table: SyncTable,
+    rows: Vec<T>,
+    sync_cursor: i64,
+    event_sequence: i64,
+    resume_token: Option<String>,
+}
+
+impl<T: SyncRow> SnapshotPage<T> {
+    /// Builds a page from rows fetched with one more than `page_size`, like [`SyncPage::new`].
+    fn new(mut rows: Vec<T>, token: SnapshotToken, page_size: i64) -> Self {
+        let has_more = rows.len() as i64 > page_size;
+        rows.truncate(page_size as usize);
+
+        let next_token = match rows.last() {
+            Some(row) if has_more => Some(SnapshotToken {
+                after_id: row.id(),
+                ..token.clone()
+            }),
+            _ => token.table.next().map(|table| SnapshotToken {
+                table,
+                after_id: 0,
+                ..token.clone()
+            }),
+        };
+
+        Self {
+            table: token.table,
+            rows,
+            sync_cursor: token.sync_cursor,
+            event_sequence: token.event_sequence,
+            resume_token: next_token.map(|token| token.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum SyncError {
+    #[snafu(display("Limit must be between 1 and {MAX_PAGE_SIZE}"))]
+    InvalidLimit,
+    #[snafu(display("Invalid resume token"))]
+    InvalidToken,
+    #[snafu(display("Deletes since this cursor were purged, a full resync is required"))]
+    FullResyncRequired,
+    #[snafu(display("Internal server error. Please try again later."))]
+    Pool {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel_async::pooled_connection::deadpool::PoolError,
+    },
+    #[snafu(display("Internal server error. Please try again later."))]
+    Query {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+}
+
+impl ResponseError for SyncError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SyncError::InvalidLimit | SyncError::InvalidToken => StatusCode::BAD_REQUEST,
+            SyncError::FullResyncRequired => StatusCode::GONE,
+            SyncError::Pool { .. } | SyncError::Query { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let mut response_builder = HttpResponseBuilder::new(self.status_code());
+        response_builder.insert_header((header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8));
+
+        let message = self.to_string();
+        response_builder.body(message)
+    }
+}
+
+fn page_size(limit: Option<i64>) -> Result<i64, SyncError> {
+    let page_size = limit.unwrap_or(MAX_PAGE_SIZE);
+    ensure!((1..=MAX_PAGE_SIZE).contains(&page_size), InvalidLimitSnafu);
+
+    Ok(page_size)
+}
+
+#[get("/sync/{table}")]
+async fn sync_table(
+    db_pool: web::Data<DbPool>,
+    table: web::Path<SyncTable>,
+    web::Query(params): web::Query<SyncParams>,
+    user: SyncingUser,
+) -> actix_web::Result<HttpResponse, SyncError> {
+    let user_id = user.user_id;
+    let page_size = page_size(params.limit)?;
+    let range = RowRange::ChangedSince(params.since);
+    let since = params.since;
+
+    let mut conn = db_pool.get().await.context(PoolSnafu)?;
+    let conn = &mut conn;
+
+    // a first sync has nothing stale to delete, so purged tombstones don't matter to it
+    if since > 0 {
+        let purged_cursor = purged_cursor(conn, user_id).await.context(QuerySnafu)?;
+        ensure!(
+            purged_cursor.is_none_or(|purged_cursor| since >= purged_cursor),
+            FullResyncRequiredSnafu
+        );
+    }
+
+    let page = ChangedRows { since, page_size };
+    load_page(conn, *table, user_id, range, page_size + 1, page).await
+}
+
+/// Exports the whole account a page at a time, one table after another.
+///
+/// Instead of holding a Postgres snapshot open between requests, the export is pinned to the
+/// sync cursor at its start: pages only include rows that haven't changed since, so every row
+/// is returned as it was at that point, and rows changed later are picked up by the lazy sync
+/// that follows the export.
+///
+/// The pin only reads like a repeatable read snapshot because `start_snapshot` takes the
+/// user's change lock first. Cursors are handed out before their rows commit, so without it an
+/// event still in flight could commit a row at or below the pin after pages past it were sent.
+#[get("/snapshot")]
+async fn snapshot(
+    db_pool: web::Data<DbPool>,
+    web::Query(params): web::Query<SnapshotParams>,
+    user: SyncingUser,
+) -> actix_web::Result<HttpResponse, SyncError> {
+    let user_id = user.user_id;
+    let page_size = page_size(params.limit)?;
+
+    let mut conn = db_pool.get().await.context(PoolSnafu)?;
+    let conn = &mut conn;
+    let token = match params.token {
+        Some(token) => resume_snapshot(conn, user_id, token).await?,
+        None => start_snapshot(conn, user_id).await.context(QuerySnafu)?,
+    };
+    let range = RowRange::Snapshot {
+        sync_cursor: token.sync_cursor,
+        after_id: token.after_id,
+    };
+
+    let table = token.table;
+    let page = SnapshotRows { token, page_size };
+    load_page(conn, table, user_id, range, page_size + 1, page).await
+}
+
+/// The newest cursor among the user's purged tombstones, if any were purged.
+async fn purged_cursor(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+) -> Result<Option<i64>, diesel::result::Error> {
+    use app_db::schema::sync_horizons::dsl;
+    use diesel::OptionalExtension;
+
+    dsl::sync_horizons
+        .filter(dsl::user_id.eq(user_id))
+        .select(dsl::purged_cursor)
+        .get_result(conn)
+        .await
+        .optional()
+}
+
+#[derive(QueryableByName)]
+struct SequenceValue {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    last_value: i64,
+}
+
+/// How far the user's changes have got, as the latest sync cursor and event log position.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    sync_cursor: i64,
+    event_sequence: i64,
+}
+
+async fn current_position(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+) -> Result<Position, diesel::result::Error> {
+    use app_db::schema::events::dsl;
+
+    let sync_cursor = diesel::sql_query("SELECT last_value FROM sync_cursor_seq")
+        .get_result::<SequenceValue>(conn)
+        .await?
+        .last_value;
+
+    let event_sequence: Option<i64> = dsl::events
+        .filter(dsl::user_id.eq(user_id))
+        .select(diesel::dsl::max(dsl::id))
+        .get_result(conn)
+        .await?;
+
+    Ok(Position {
+        sync_cursor,
+        event_sequence: event_sequence.unwrap_or(0),
+    })
+}
+
+/// Pins a new snapshot to the latest sync cursor and event log position.
+async fn start_snapshot(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+) -> Result<SnapshotToken, diesel::result::Error> {
+    conn.transaction(|conn| {
+        async move {
+            // waits for the user's in-flight events, see `EventDb::lock_user_changes`
+            diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+                .bind::<diesel::sql_types::BigInt, _>(i64::from(user_id))
+                .execute(conn)
+                .await?;
+
+            let position = current_position(conn, user_id).await?;
+
+            Ok(SnapshotToken {
+                sync_cursor: position.sync_cursor,
+                event_sequence: position.event_sequence,
+                table: SyncTable::ALL[0],
+                after_id: 0,
+            })
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Checks a resume token the client passed back before continuing its export.
+async fn resume_snapshot(
+    conn: &mut AsyncPgConnection,
+    user_id: i32,
+    token: SnapshotToken,
+) -> Result<SnapshotToken, SyncError> {
+    let position = current_position(conn, user_id).await.context(QuerySnafu)?;
+    let purged_cursor = purged_cursor(conn, user_id).await.context(QuerySnafu)?;
+    check_resumable(&token, position, purged_cursor)?;
+
+    Ok(token)
+}
+
+/// Tokens aren't signed, so one pinned past anything the server has handed out is rejected.
+/// One pinned before tombstones were purged has to start over, since the lazy sync after it
+/// could no longer report deletes of rows already exported.
+fn check_resumable(
+    token: &SnapshotToken,
+    position: Position,
+    purged_cursor: Option<i64>,
+) -> Result<(), SyncError> {
+    ensure!(
+        (0..=position.sync_cursor).contains(&token.sync_cursor)
+            && (0..=position.event_sequence).contains(&token.event_sequence),
+        InvalidTokenSnafu
+    );
+    ensure!(
+        purged_cursor.is_none_or(|purged_cursor| token.sync_cursor >= purged_cursor),
+        FullResyncRequiredSnafu
+    );
+
+    Ok(())
+}
+
+/// Turns a page of rows, whichever table they were loaded from, into the response.
+trait RespondWithRows {
+    fn respond<T: SyncRow + Serialize>(self, rows: Vec<T>) -> HttpResponse;
+}
+
+/// Rows for a lazy sync from `since`.
+struct ChangedRows {
+    since: i64,
+    page_size: i64,
+}
+
+impl RespondWithRows for ChangedRows {
+    fn respond<T: SyncRow + Serialize>(self, rows: Vec<T>) -> HttpResponse {
+        HttpResponse::Ok().json(SyncPage::new(rows, self.since, self.page_size))
+    }
+}
+
+/// Rows for the snapshot page `token` points at.
+struct SnapshotRows {
+    token: SnapshotToken,
+    page_size: i64,
+}
+
+impl RespondWithRows for SnapshotRows {
+    fn respond<T: SyncRow + Serialize>(self, rows: Vec<T>) -> HttpResponse {
+        HttpResponse::Ok().json(SnapshotPage::new(rows, self.token, self.page_size))
+    }
+}
+
+/// Generates [`load_page`] for the synced tables. Their paging columns are all named the same,
+/// so only the schema table and row type differ between them.
+macro_rules! sync_tables {
+    ($($variant:ident => $table:ident, $row:ty);* $(;)?) => {
+        /// Loads up to `limit` of the user's rows in `range` from `table` and hands them to
+        /// `page` to respond with.
+        async fn load_page(
+            conn: &mut AsyncPgConnection,
+            table: SyncTable,
+            user_id: i32,
+            range: RowRange,
+            limit: i64,
+            page: impl RespondWithRows,
+        ) -> Result<HttpResponse, SyncError> {
+            match table {
+                $(
+                    SyncTable::$variant => {
+                        use app_db::schema::$table::dsl;
+
+                        let query = dsl::$table
+                            .filter(dsl::user_id.eq(user_id))
+                            .select(<$row>::as_select())
+                            .limit(limit)
+                            .into_boxed();
+
+                        let query = match range {
+                            RowRange::ChangedSince(since) => query
+                                .filter(dsl::sync_cursor.gt(since))
+                                .order(dsl::sync_cursor.asc()),
+                            RowRange::Snapshot {
+                                sync_cursor,
+                                after_id,
+                            } => query
+                                .filter(dsl::sync_cursor.le(sync_cursor))
+                                .filter(dsl::id.gt(after_id))
+                                .order(dsl::id.asc()),
+                        };
+
+                        let rows: Vec<$row> = query.load(conn).await.context(QuerySnafu)?;
+                        Ok(page.respond(rows))
+                    }
+                )*
+            }
+        }
+    };
+}
+
+sync_tables!(
+    Projects => projects, ProjectRow;
+    Sections => sections, SectionRow;
+    SectionItems => section_items, SectionItemRow;
+    SectionItemNotes => section_item_notes, SectionItemNoteRow;
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A row whose id and cursor are the same number.
+    impl SyncRow for i64 {
+        fn id(&self) -> i32 {
+            *self as i32
+        }
+
+        fn cursor(&self) -> i64 {
+            *self
+        }
+    }
+
+    fn test_token(table: SyncTable) -> SnapshotToken {
+        SnapshotToken {
+            sync_cursor: 40,
+            event_sequence: 12,
+            table,
+            after_id: 0,
+        }
+    }
+
+    #[test]
+    fn page_with_more_rows() {
+        let page = SyncPage::new(vec![4, 7, 9], 2, 2);
+        assert_eq!(page.rows, vec![4, 7]);
+        assert_eq!(page.next_cursor, 7);
+        assert!(page.has_more);
+    }
+
+    #[test]
+    fn last_page() {
+        let page = SyncPage::new(vec![4, 7], 2, 2);
+        assert_eq!(page.next_cursor, 7);
+        assert!(!page.has_more);
+    }
+
+    #[test]
+    fn empty_page_keeps_cursor() {
+        let page = SyncPage::<i64>::new(Vec::new(), 12, 2);
+        assert!(page.rows.is_empty());
+        assert_eq!(page.next_cursor, 12);
+        assert!(!page.has_more);
+    }
+
+    #[test]
+    fn snapshot_token_round_trip() {
+        let token = SnapshotToken {
+            after_id: 31,
+            ..test_token(SyncTable::SectionItems)
+        };
+
+        let parsed: SnapshotToken = token.to_string().parse().unwrap();
+        assert_eq!(parsed, token);
+    }
+
+    #[test]
+    fn reject_malformed_snapshot_token() {
+        assert!("40.12.fabrics.0".parse::<SnapshotToken>().is_err());
+        assert!("40.12.projects".parse::<SnapshotToken>().is_err());
+        assert!("forty.12.projects.0".parse::<SnapshotToken>().is_err());
+    }
+
+    fn test_position() -> Position {
+        Position {
+            sync_cursor: 50,
+            event_sequence: 20,
+        }
+    }
+
+    #[test]
+    fn resume_snapshot_token() {
+        let token = test_token(SyncTable::Sections);
+        assert!(check_resumable(&token, test_position(), Some(40)).is_ok());
+    }
+
+    #[test]
+    fn reject_snapshot_token_ahead_of_server() {
+        let token = SnapshotToken {
+            sync_cursor: 51,
+            ..test_token(SyncTable::Sections)
+        };
+        assert!(matches!(
+            check_resumable(&token, test_position(), None),
+            Err(SyncError::InvalidToken)
+        ));
+
+        let token = SnapshotToken {
+            event_sequence: 21,
+            ..test_token(SyncTable::Sections)
+        };
+        assert!(matches!(
+            check_resumable(&token, test_position(), None),
+            Err(SyncError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn restart_snapshot_pinned_before_purge() {
+        let token = test_token(SyncTable::Sections);
+        assert!(matches!(
+            check_resumable(&token, test_position(), Some(41)),
+            Err(SyncError::FullResyncRequired)
+        ));
+    }
+
+    #[test]
+    fn snapshot_page_resumes_after_last_row() {
+        let page = SnapshotPage::new(vec![4, 7, 9], test_token(SyncTable::Sections), 2);
+
+        let token: SnapshotToken = page.resume_token.unwrap().parse().unwrap();
+        assert_eq!(token.table, SyncTable::Sections);
+        assert_eq!(token.after_id, 7);
+        assert_eq!(token.sync_cursor, 40);
+    }
+
+    #[test]
+    fn snapshot_moves_to_next_table() {
+        let page = SnapshotPage::new(vec![4], test_token(SyncTable::Sections), 2);
+
+        let token: SnapshotToken = page.resume_token.unwrap().parse().unwrap();
+        assert_eq!(token.table, SyncTable::SectionItems);
+        assert_eq!(token.after_id, 0);
+    }
+
+    #[test]
+    fn snapshot_ends_after_last_table() {
+        let page = SnapshotPage::<i64>::new(Vec::new(), test_token(SyncTable::SectionItemNotes), 2);
+        assert!(page.resume_token.is_none());
+    }
+}
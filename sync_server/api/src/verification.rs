@@ -0,0 +1,88 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// What an account that hasn't verified its email address may still do, counted from when it
+/// signed up. Verified accounts are never limited.
+#[derive(Debug, Clone, Copy)]
+pub struct VerificationPolicy {
+    /// How long an unverified account may keep syncing. `None` lets it sync indefinitely.
+    pub sync_grace_period: Option<Duration>,
+    /// How long an unverified account may keep logging in. `None` lets it log in indefinitely.
+    pub login_grace_period: Option<Duration>,
+}
+
+impl Default for VerificationPolicy {
+    fn default() -> Self {
+        Self {
+            sync_grace_period: Some(Duration::days(7)),
+            login_grace_period: None,
+        }
+    }
+}
+
+impl VerificationPolicy {
+    pub fn may_sync(
+        &self,
+        created_at: DateTime<Utc>,
+        verified_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        allowed(self.sync_grace_period, created_at, verified_at, now)
+    }
+
+    pub fn may_log_in(
+        &self,
+        created_at: DateTime<Utc>,
+        verified_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        allowed(self.login_grace_period, created_at, verified_at, now)
+    }
+}
+
+fn allowed(
+    grace_period: Option<Duration>,
+    created_at: DateTime<Utc>,
+    verified_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    match (verified_at, grace_period) {
+        (Some(_), _) | (None, None) => true,
+        (None, Some(grace_period)) => now < created_at + grace_period,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> VerificationPolicy {
+        VerificationPolicy {
+            sync_grace_period: Some(Duration::days(7)),
+            login_grace_period: None,
+        }
+    }
+
+    #[test]
+    fn unverified_account_syncs_during_grace_period() {
+        let created_at = Utc::now();
+
+        assert!(policy().may_sync(created_at, None, created_at + Duration::days(6)));
+        assert!(!policy().may_sync(created_at, None, created_at + Duration::days(8)));
+    }
+
+    #[test]
+    fn verified_account_is_never_limited() {
+        let created_at = Utc::now();
+        let later = created_at + Duration::days(30);
+
+        assert!(policy().may_sync(created_at, Some(created_at), later));
+        assert!(policy().may_log_in(created_at, Some(created_at), later));
+    }
+
+    #[test]
+    fn no_grace_period_means_no_limit() {
+        let created_at = Utc::now();
+
+        assert!(policy().may_log_in(created_at, None, created_at + Duration::days(365)));
+    }
+}
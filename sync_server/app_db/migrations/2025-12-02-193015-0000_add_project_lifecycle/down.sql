@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS projects_user_project_index;
+
+ALTER TABLE projects DROP COLUMN is_deleted;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE projects ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT false;
+
+CREATE UNIQUE INDEX IF NOT EXISTS projects_user_project_index ON projects(user_id, project_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS sections_project_index;
+DROP INDEX IF EXISTS sections_user_section_index;
+
+DROP TABLE sections;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS sections (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    project_id INTEGER NOT NULL,
+    section_id INTEGER NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    is_deleted BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (user_id, project_id) REFERENCES projects(user_id, project_id)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS sections_user_section_index ON sections(user_id, section_id);
+CREATE INDEX IF NOT EXISTS sections_project_index ON sections(user_id, project_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS section_items_section_index;
+DROP INDEX IF EXISTS section_items_user_item_index;
+
+DROP TABLE section_items;
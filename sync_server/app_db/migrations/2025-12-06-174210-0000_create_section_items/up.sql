@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS section_items (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    section_id INTEGER NOT NULL,
+    item_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    is_complete BOOLEAN NOT NULL DEFAULT false,
+    item_order INTEGER NOT NULL,
+    is_deleted BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (user_id, section_id) REFERENCES sections(user_id, section_id)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS section_items_user_item_index ON section_items(user_id, item_id);
+CREATE INDEX IF NOT EXISTS section_items_section_index ON section_items(user_id, section_id);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS section_item_notes_item_index;
+DROP INDEX IF EXISTS section_item_notes_user_note_index;
+
+DROP TABLE section_item_notes;
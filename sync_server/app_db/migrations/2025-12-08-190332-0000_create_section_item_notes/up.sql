@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS section_item_notes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    item_id INTEGER NOT NULL,
+    note_id INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    is_deleted BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id),
+    FOREIGN KEY (user_id, item_id) REFERENCES section_items(user_id, item_id)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS section_item_notes_user_note_index ON section_item_notes(user_id, note_id);
+CREATE INDEX IF NOT EXISTS section_item_notes_item_index ON section_item_notes(user_id, item_id);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+CREATE UNIQUE INDEX IF NOT EXISTS events_user_event_id_index ON events(user_id, event_id);
@@ -0,0 +1,18 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS section_item_notes_sync_cursor_index;
+DROP INDEX IF EXISTS section_items_sync_cursor_index;
+DROP INDEX IF EXISTS sections_sync_cursor_index;
+DROP INDEX IF EXISTS projects_sync_cursor_index;
+
+DROP TRIGGER IF EXISTS section_item_notes_sync_cursor ON section_item_notes;
+DROP TRIGGER IF EXISTS section_items_sync_cursor ON section_items;
+DROP TRIGGER IF EXISTS sections_sync_cursor ON sections;
+DROP TRIGGER IF EXISTS projects_sync_cursor ON projects;
+
+ALTER TABLE section_item_notes DROP COLUMN sync_cursor;
+ALTER TABLE section_items DROP COLUMN sync_cursor;
+ALTER TABLE sections DROP COLUMN sync_cursor;
+ALTER TABLE projects DROP COLUMN sync_cursor;
+
+DROP FUNCTION IF EXISTS bump_sync_cursor();
+DROP SEQUENCE IF EXISTS sync_cursor_seq;
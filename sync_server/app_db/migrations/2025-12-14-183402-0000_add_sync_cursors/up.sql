@@ -0,0 +1,28 @@
+-- Your SQL goes here
+CREATE SEQUENCE IF NOT EXISTS sync_cursor_seq;
+
+CREATE OR REPLACE FUNCTION bump_sync_cursor() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.sync_cursor := nextval('sync_cursor_seq');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE projects ADD COLUMN sync_cursor BIGINT NOT NULL DEFAULT nextval('sync_cursor_seq');
+ALTER TABLE sections ADD COLUMN sync_cursor BIGINT NOT NULL DEFAULT nextval('sync_cursor_seq');
+ALTER TABLE section_items ADD COLUMN sync_cursor BIGINT NOT NULL DEFAULT nextval('sync_cursor_seq');
+ALTER TABLE section_item_notes ADD COLUMN sync_cursor BIGINT NOT NULL DEFAULT nextval('sync_cursor_seq');
+
+CREATE TRIGGER projects_sync_cursor BEFORE UPDATE ON projects
+    FOR EACH ROW EXECUTE FUNCTION bump_sync_cursor();
+CREATE TRIGGER sections_sync_cursor BEFORE UPDATE ON sections
+    FOR EACH ROW EXECUTE FUNCTION bump_sync_cursor();
+CREATE TRIGGER section_items_sync_cursor BEFORE UPDATE ON section_items
+    FOR EACH ROW EXECUTE FUNCTION bump_sync_cursor();
+CREATE TRIGGER section_item_notes_sync_cursor BEFORE UPDATE ON section_item_notes
+    FOR EACH ROW EXECUTE FUNCTION bump_sync_cursor();
+
+CREATE INDEX IF NOT EXISTS projects_sync_cursor_index ON projects(user_id, sync_cursor);
+CREATE INDEX IF NOT EXISTS sections_sync_cursor_index ON sections(user_id, sync_cursor);
+CREATE INDEX IF NOT EXISTS section_items_sync_cursor_index ON section_items(user_id, sync_cursor);
+CREATE INDEX IF NOT EXISTS section_item_notes_sync_cursor_index ON section_item_notes(user_id, sync_cursor);
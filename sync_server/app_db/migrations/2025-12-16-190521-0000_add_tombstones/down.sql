@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE sync_horizons;
+
+DROP INDEX IF EXISTS section_item_notes_deleted_at_index;
+DROP INDEX IF EXISTS section_items_deleted_at_index;
+DROP INDEX IF EXISTS sections_deleted_at_index;
+DROP INDEX IF EXISTS projects_deleted_at_index;
+
+ALTER TABLE section_item_notes DROP COLUMN deleted_at;
+ALTER TABLE section_items DROP COLUMN deleted_at;
+ALTER TABLE sections DROP COLUMN deleted_at;
+ALTER TABLE projects DROP COLUMN deleted_at;
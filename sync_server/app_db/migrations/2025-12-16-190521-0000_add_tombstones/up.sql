@@ -0,0 +1,35 @@
+-- Your SQL goes here
+ALTER TABLE projects ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE sections ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE section_items ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE section_item_notes ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- backfilling isn't a change clients need to sync
+ALTER TABLE projects DISABLE TRIGGER projects_sync_cursor;
+ALTER TABLE sections DISABLE TRIGGER sections_sync_cursor;
+ALTER TABLE section_items DISABLE TRIGGER section_items_sync_cursor;
+ALTER TABLE section_item_notes DISABLE TRIGGER section_item_notes_sync_cursor;
+
+UPDATE projects SET deleted_at = updated_at WHERE is_deleted;
+UPDATE sections SET deleted_at = updated_at WHERE is_deleted;
+UPDATE section_items SET deleted_at = updated_at WHERE is_deleted;
+UPDATE section_item_notes SET deleted_at = updated_at WHERE is_deleted;
+
+ALTER TABLE projects ENABLE TRIGGER projects_sync_cursor;
+ALTER TABLE sections ENABLE TRIGGER sections_sync_cursor;
+ALTER TABLE section_items ENABLE TRIGGER section_items_sync_cursor;
+ALTER TABLE section_item_notes ENABLE TRIGGER section_item_notes_sync_cursor;
+
+CREATE INDEX IF NOT EXISTS projects_deleted_at_index ON projects(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS sections_deleted_at_index ON sections(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS section_items_deleted_at_index ON section_items(deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX IF NOT EXISTS section_item_notes_deleted_at_index ON section_item_notes(deleted_at) WHERE deleted_at IS NOT NULL;
+
+-- the newest sync cursor among each user's purged rows, a client that last synced before it
+-- may have missed a delete
+CREATE TABLE IF NOT EXISTS sync_horizons (
+    user_id INTEGER PRIMARY KEY,
+    purged_cursor BIGINT NOT NULL,
+    purged_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE events DROP COLUMN hlc;
+
+ALTER TABLE section_item_notes DROP COLUMN text_clock;
+ALTER TABLE section_items DROP COLUMN is_complete_clock;
+ALTER TABLE section_items DROP COLUMN text_clock;
+ALTER TABLE sections DROP COLUMN name_clock;
+ALTER TABLE projects DROP COLUMN completed_clock;
+ALTER TABLE projects DROP COLUMN title_clock;
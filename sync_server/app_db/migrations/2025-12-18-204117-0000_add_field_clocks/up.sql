@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- each clock is the encoded hybrid logical clock of the edit that last wrote the field, the
+-- empty string sorts before any edit
+ALTER TABLE projects ADD COLUMN title_clock VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE projects ADD COLUMN completed_clock VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE sections ADD COLUMN name_clock VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE section_items ADD COLUMN text_clock VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE section_items ADD COLUMN is_complete_clock VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE section_item_notes ADD COLUMN text_clock VARCHAR(255) NOT NULL DEFAULT '';
+
+ALTER TABLE events ADD COLUMN hlc VARCHAR(255) NOT NULL DEFAULT '';
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN verified_at TIMESTAMPTZ;
+
+-- accounts from before verification existed were never sent an email to confirm
+UPDATE users SET verified_at = created_at;
+
+CREATE TABLE email_verifications (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    -- sha-256 of the token in hex, the token itself is only ever in the email
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX email_verifications_user_id_index ON email_verifications(user_id);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE section_item_notes ALTER COLUMN text_clock TYPE VARCHAR(255) COLLATE "default";
+ALTER TABLE section_items ALTER COLUMN is_complete_clock TYPE VARCHAR(255) COLLATE "default";
+ALTER TABLE section_items ALTER COLUMN text_clock TYPE VARCHAR(255) COLLATE "default";
+ALTER TABLE sections ALTER COLUMN name_clock TYPE VARCHAR(255) COLLATE "default";
+ALTER TABLE projects ALTER COLUMN completed_clock TYPE VARCHAR(255) COLLATE "default";
+ALTER TABLE projects ALTER COLUMN title_clock TYPE VARCHAR(255) COLLATE "default";
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- clocks are compared in SQL to pick the last writer, and that has to agree with how `Hlc`
+-- orders them in Rust, which is byte order rather than the database's collation
+ALTER TABLE projects ALTER COLUMN title_clock TYPE VARCHAR(255) COLLATE "C";
+ALTER TABLE projects ALTER COLUMN completed_clock TYPE VARCHAR(255) COLLATE "C";
+ALTER TABLE sections ALTER COLUMN name_clock TYPE VARCHAR(255) COLLATE "C";
+ALTER TABLE section_items ALTER COLUMN text_clock TYPE VARCHAR(255) COLLATE "C";
+ALTER TABLE section_items ALTER COLUMN is_complete_clock TYPE VARCHAR(255) COLLATE "C";
+ALTER TABLE section_item_notes ALTER COLUMN text_clock TYPE VARCHAR(255) COLLATE "C";
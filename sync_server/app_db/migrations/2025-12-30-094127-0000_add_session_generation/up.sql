@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- bumped whenever all of a user's sessions are ended, so websocket tickets signed before
+-- that stop working even though they haven't expired
+ALTER TABLE users ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0;
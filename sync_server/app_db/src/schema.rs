@@ -1,5 +1,58 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 255]
+        name -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    email_verifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    events (id) {
+        id -> Int8,
+        #[max_length = 255]
+        event_id -> Varchar,
+        user_id -> Int4,
+        #[max_length = 255]
+        event_type -> Varchar,
+        payload -> Jsonb,
+        schema_version -> Int4,
+        received_at -> Timestamptz,
+        #[max_length = 255]
+        hlc -> Varchar,
+    }
+}
+
+diesel::table! {
+    password_resets (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     projects (id) {
         id -> Int4,
@@ -10,6 +63,77 @@ diesel::table! {
         completed -> Bool,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        is_deleted -> Bool,
+        sync_cursor -> Int8,
+        deleted_at -> Nullable<Timestamptz>,
+        #[max_length = 255]
+        title_clock -> Varchar,
+        #[max_length = 255]
+        completed_clock -> Varchar,
+    }
+}
+
+diesel::table! {
+    section_item_notes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        item_id -> Int4,
+        note_id -> Int4,
+        text -> Text,
+        is_deleted -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        sync_cursor -> Int8,
+        deleted_at -> Nullable<Timestamptz>,
+        #[max_length = 255]
+        text_clock -> Varchar,
+    }
+}
+
+diesel::table! {
+    section_items (id) {
+        id -> Int4,
+        user_id -> Int4,
+        section_id -> Int4,
+        item_id -> Int4,
+        text -> Text,
+        is_complete -> Bool,
+        item_order -> Int4,
+        is_deleted -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        sync_cursor -> Int8,
+        deleted_at -> Nullable<Timestamptz>,
+        #[max_length = 255]
+        text_clock -> Varchar,
+        #[max_length = 255]
+        is_complete_clock -> Varchar,
+    }
+}
+
+diesel::table! {
+    sections (id) {
+        id -> Int4,
+        user_id -> Int4,
+        project_id -> Int4,
+        section_id -> Int4,
+        #[max_length = 255]
+        name -> Varchar,
+        is_deleted -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        sync_cursor -> Int8,
+        deleted_at -> Nullable<Timestamptz>,
+        #[max_length = 255]
+        name_clock -> Varchar,
+    }
+}
+
+diesel::table! {
+    sync_horizons (user_id) {
+        user_id -> Int4,
+        purged_cursor -> Int8,
+        purged_at -> Timestamptz,
     }
 }
 
@@ -22,9 +146,30 @@ diesel::table! {
         password_hash -> Varchar,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        verified_at -> Nullable<Timestamptz>,
+        session_generation -> Int4,
     }
 }
 
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(email_verifications -> users (user_id));
+diesel::joinable!(events -> users (user_id));
+diesel::joinable!(password_resets -> users (user_id));
 diesel::joinable!(projects -> users (user_id));
+diesel::joinable!(section_item_notes -> users (user_id));
+diesel::joinable!(section_items -> users (user_id));
+diesel::joinable!(sections -> users (user_id));
+diesel::joinable!(sync_horizons -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(projects, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    email_verifications,
+    events,
+    password_resets,
+    projects,
+    section_item_notes,
+    section_items,
+    sections,
+    sync_horizons,
+    users,
+);
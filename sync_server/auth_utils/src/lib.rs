@@ -1,7 +1,13 @@
 use argon2::{
     Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
-    password_hash::{SaltString, rand_core::OsRng},
+    password_hash::{
+        SaltString,
+        rand_core::{OsRng, RngCore},
+    },
 };
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
 use snafu::prelude::*;
 
 #[derive(Debug, Snafu)]
@@ -21,26 +27,50 @@ pub fn generate_password_hash(password: &str) -> Result<String, GenerateHashErro
         .context(GenerateHashSnafu {})?
         .to_string();
 
-    return Ok(password);
+    Ok(password)
 }
 
 pub fn compare_passwords(password: &str, password_hash: &str) -> PasswordVerify {
     let argon = Argon2::default();
-    let parsed_hash = match PasswordHash::new(&password_hash) {
+    let parsed_hash = match PasswordHash::new(password_hash) {
         Ok(hash) => hash,
         // TODO: add logging
         Err(_) => return PasswordVerify::NoMatch,
     };
 
-    let result = match argon.verify_password(password.as_bytes(), &parsed_hash) {
+    match argon.verify_password(password.as_bytes(), &parsed_hash) {
         Ok(_) => PasswordVerify::Match,
         Err(_) => PasswordVerify::NoMatch,
-    };
-
-    result
+    }
 }
 
 pub enum PasswordVerify {
     Match,
     NoMatch,
 }
+
+/// A freshly generated secret token and the hash to store for it. The token is handed to the
+/// client once and only the hash is kept.
+pub struct GeneratedToken {
+    pub token: String,
+    pub token_hash: String,
+}
+
+/// Generates a random token for authenticating without a password, e.g. an API token.
+pub fn generate_token() -> GeneratedToken {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    let token_hash = hash_token(&token);
+
+    GeneratedToken { token, token_hash }
+}
+
+/// Hashes a token for storage and lookup. Tokens are random rather than chosen by people, so
+/// a fast unsalted hash is enough and lets a token be found by its hash.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -0,0 +1,169 @@
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// A hybrid logical clock timestamp: the device's wall clock in milliseconds, a counter that
+/// orders events within the same millisecond, and the id of the device that made the edit.
+/// Timestamps compare in that order, so concurrent edits from different devices always
+/// resolve the same way.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Hlc {
+    wall_time: u64,
+    counter: u32,
+    node: String,
+}
+
+impl Hlc {
+    /// Longest node id accepted from a client. The encoded clock is stored in `VARCHAR(255)`
+    /// columns and the wall time and counter take up 26 of those.
+    pub const MAX_NODE_LENGTH: usize = 64;
+
+    pub fn new(wall_time: u64, counter: u32, node: impl Into<String>) -> Self {
+        Self {
+            wall_time,
+            counter,
+            node: node.into(),
+        }
+    }
+
+    pub fn wall_time(&self) -> u64 {
+        self.wall_time
+    }
+
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Fixed width text that sorts the same way the timestamps compare, so stored clocks can
+    /// be compared in SQL.
+    pub fn encode(&self) -> String {
+        format!("{:016x}-{:08x}-{}", self.wall_time, self.counter, self.node)
+    }
+}
+
+/// The server's hybrid logical clock, for stamping events from clients that don't send one.
+/// It never goes backwards or hands out the same timestamp twice, and every client clock it
+/// sees moves it forward, so a server stamp orders after the edits the server already took in.
+pub struct ServerClock {
+    last: Mutex<Hlc>,
+}
+
+impl Default for ServerClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerClock {
+    pub const NODE: &str = "server";
+
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new(Hlc::new(0, 0, Self::NODE)),
+        }
+    }
+
+    /// The server's wall clock in milliseconds since the epoch.
+    pub fn wall_time() -> u64 {
+        chrono::Utc::now().timestamp_millis().max(0) as u64
+    }
+
+    /// A timestamp later than any this clock has handed out or seen.
+    pub fn now(&self) -> Hlc {
+        self.tick(Self::wall_time())
+    }
+
+    /// Moves the clock up to a timestamp received from a client.
+    pub fn observe(&self, clock: &Hlc) {
+        let mut last = self.last.lock().expect("clock lock isn't poisoned");
+        if (clock.wall_time, clock.counter) > (last.wall_time, last.counter) {
+            last.wall_time = clock.wall_time;
+            last.counter = clock.counter;
+        }
+    }
+
+    fn tick(&self, wall_time: u64) -> Hlc {
+        let mut last = self.last.lock().expect("clock lock isn't poisoned");
+        if wall_time > last.wall_time {
+            last.wall_time = wall_time;
+            last.counter = 0;
+        } else {
+            // same millisecond, or the wall clock stepped back
+            match last.counter.checked_add(1) {
+                Some(counter) => last.counter = counter,
+                None => {
+                    last.wall_time += 1;
+                    last.counter = 0;
+                }
+            }
+        }
+
+        last.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_orders_within_a_millisecond() {
+        let earlier = Hlc::new(1_700_000_000_000, 0, "iphone");
+        let later = Hlc::new(1_700_000_000_000, 1, "iphone");
+
+        assert!(earlier < later);
+        assert!(earlier.encode() < later.encode());
+    }
+
+    #[test]
+    fn node_breaks_ties() {
+        let ipad = Hlc::new(1_700_000_000_000, 3, "ipad");
+        let iphone = Hlc::new(1_700_000_000_000, 3, "iphone");
+
+        assert!(ipad < iphone);
+        assert!(ipad.encode() < iphone.encode());
+    }
+
+    #[test]
+    fn encoding_sorts_like_clocks() {
+        let clocks = [
+            Hlc::new(9, 0, "b"),
+            Hlc::new(10, 0, "a"),
+            Hlc::new(0x1_0000_0000, 0, "a"),
+            Hlc::new(0x1_0000_0000, 0xffff, "a"),
+        ];
+
+        for pair in clocks.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].encode() < pair[1].encode());
+        }
+    }
+
+    #[test]
+    fn server_clock_orders_stamps_within_a_millisecond() {
+        let clock = ServerClock::new();
+
+        let first = clock.tick(1_700_000_000_000);
+        let second = clock.tick(1_700_000_000_000);
+        assert!(first < second);
+        assert!(first.encode() < second.encode());
+    }
+
+    #[test]
+    fn server_clock_never_goes_backwards() {
+        let clock = ServerClock::new();
+
+        let first = clock.tick(1_700_000_000_000);
+        let second = clock.tick(1_699_999_999_000);
+        assert!(first < second);
+    }
+
+    #[test]
+    fn server_clock_moves_past_observed_clocks() {
+        let clock = ServerClock::new();
+        let client = Hlc::new(1_700_000_000_500, 4, "ipad");
+
+        clock.observe(&client);
+        assert!(clock.tick(1_700_000_000_000) > client);
+    }
+}
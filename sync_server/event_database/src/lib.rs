@@ -1,21 +1,363 @@
-use diesel::ExpressionMethods;
-use diesel_async::{RunQueryDsl, pg};
+mod hlc;
+
+use diesel::result::DatabaseErrorKind;
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+use diesel_async::{AnsiTransactionManager, AsyncConnection, RunQueryDsl, TransactionManager, pg};
 use serde::{Deserialize, Serialize};
+use snafu::{Location, ResultExt, prelude::*};
+
+pub use crate::hlc::{Hlc, ServerClock};
+
+#[derive(Debug, Snafu)]
+pub enum EventDbError {
+    #[snafu(display("Database error"))]
+    Database {
+        #[snafu(implicit)]
+        location: Location,
+        source: diesel::result::Error,
+    },
+    #[snafu(display("No {entity} found for event"))]
+    NotFound { entity: &'static str },
+    #[snafu(display("Reordered items don't match the items in section {section_id}"))]
+    ReorderMismatch { section_id: i32 },
+    #[snafu(display("The {field} was already replaced by a newer edit or a delete"))]
+    Superseded { field: &'static str },
+    #[snafu(display("The {entity} belongs to another user"))]
+    NotOwned { entity: &'static str },
+}
+
+impl From<diesel::result::Error> for EventDbError {
+    #[track_caller]
+    fn from(source: diesel::result::Error) -> Self {
+        EventDbError::Database {
+            location: snafu::GenerateImplicitData::generate(),
+            source,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateProjectEventData {
-    user_id: i32,
     project_id: i32,
     title: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateProjectTitleEventData {
+    project_id: i32,
+    title: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetProjectCompletedEventData {
+    project_id: i32,
+    completed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteProjectEventData {
+    project_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateSectionEventData {
+    project_id: i32,
+    section_id: i32,
+    name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameSectionEventData {
+    section_id: i32,
+    name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteSectionEventData {
+    section_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateSectionItemEventData {
+    section_id: i32,
+    item_id: i32,
+    text: String,
+    order: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateSectionItemTextEventData {
+    item_id: i32,
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetSectionItemCompletedEventData {
+    item_id: i32,
+    is_complete: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteSectionItemEventData {
+    item_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddSectionItemNoteEventData {
+    item_id: i32,
+    note_id: i32,
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditSectionItemNoteEventData {
+    note_id: i32,
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoveSectionItemNoteEventData {
+    note_id: i32,
+}
+
+/// The full order of a section's items, where each item's order is its index in `item_ids`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorderSectionItemsEventData {
+    section_id: i32,
+    item_ids: Vec<i32>,
+}
+
+/// An event exactly as it was received, stored in the append only event log.
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    pub event_id: String,
+    pub user_id: i32,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub schema_version: i32,
+    /// The event's hybrid logical clock, encoded with [`Hlc::encode`].
+    pub hlc: String,
+}
+
+const PURGE_SECTION_ITEM_NOTES: &str = "
+    DELETE FROM section_item_notes n
+    WHERE n.deleted_at < $1 OR EXISTS (
+        SELECT 1 FROM section_items i
+        JOIN sections s ON s.user_id = i.user_id AND s.section_id = i.section_id
+        JOIN projects p ON p.user_id = s.user_id AND p.project_id = s.project_id
+        WHERE i.user_id = n.user_id AND i.item_id = n.item_id
+            AND (i.deleted_at < $1 OR s.deleted_at < $1 OR p.deleted_at < $1)
+    )
+    RETURNING n.user_id, n.sync_cursor";
+
+const PURGE_SECTION_ITEMS: &str = "
+    DELETE FROM section_items i
+    WHERE i.deleted_at < $1 OR EXISTS (
+        SELECT 1 FROM sections s
+        JOIN projects p ON p.user_id = s.user_id AND p.project_id = s.project_id
+        WHERE s.user_id = i.user_id AND s.section_id = i.section_id
+            AND (s.deleted_at < $1 OR p.deleted_at < $1)
+    )
+    RETURNING i.user_id, i.sync_cursor";
+
+const PURGE_SECTIONS: &str = "
+    DELETE FROM sections s
+    WHERE s.deleted_at < $1 OR EXISTS (
+        SELECT 1 FROM projects p
+        WHERE p.user_id = s.user_id AND p.project_id = s.project_id AND p.deleted_at < $1
+    )
+    RETURNING s.user_id, s.sync_cursor";
+
+const PURGE_PROJECTS: &str = "
+    DELETE FROM projects p
+    WHERE p.deleted_at < $1
+    RETURNING p.user_id, p.sync_cursor";
+
+/// Wraps a purge so the newest cursor it deleted for each user is kept as their sync horizon.
+fn purge_statement(delete: &str) -> String {
+    format!(
+        "WITH purged AS ({delete})
+        INSERT INTO sync_horizons (user_id, purged_cursor, purged_at)
+        SELECT user_id, MAX(sync_cursor), NOW() FROM purged GROUP BY user_id
+        ON CONFLICT (user_id) DO UPDATE SET
+            purged_cursor = GREATEST(sync_horizons.purged_cursor, EXCLUDED.purged_cursor),
+            purged_at = EXCLUDED.purged_at"
+    )
+}
+
+/// Turns the row count of an update guarded by a field's clock into the event's outcome. When
+/// nothing was updated, `exists` is only awaited to tell a missing row from one where a later
+/// edit already won, or that was deleted. Edits never bring a tombstoned row back.
+async fn guarded_update(
+    count: usize,
+    exists: impl Future<Output = diesel::QueryResult<bool>>,
+    entity: &'static str,
+    field: &'static str,
+) -> Result<(), EventDbError> {
+    if count > 0 {
+        return Ok(());
+    }
+
+    if exists.await.context(DatabaseSnafu)? {
+        return SupersededSnafu { field }.fail();
+    }
+
+    NotFoundSnafu { entity }.fail()
+}
+
+/// Turns the result of inserting a row under a parent into the event's outcome. The composite
+/// foreign keys reject a parent that only another user has the same way as a missing one, so
+/// on a violation `owned_elsewhere` is awaited to tell the two apart. The insert has to run in
+/// a savepoint for the transaction to still be usable by then.
+async fn insert_under_parent(
+    inserted: diesel::QueryResult<usize>,
+    owned_elsewhere: impl Future<Output = diesel::QueryResult<bool>>,
+    parent: &'static str,
+) -> Result<(), EventDbError> {
+    match inserted {
+        Ok(count) => {
+            debug_assert_eq!(count, 1);
+            Ok(())
+        }
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info)) => {
+            if owned_elsewhere.await.context(DatabaseSnafu)? {
+                return NotOwnedSnafu { entity: parent }.fail();
+            }
+
+            Err(diesel::result::Error::DatabaseError(
+                DatabaseErrorKind::ForeignKeyViolation,
+                info,
+            ))
+            .context(DatabaseSnafu)
+        }
+        Err(source) => Err(source).context(DatabaseSnafu),
+    }
+}
+
+/// Applies events to the database. Each handler only touches the rows of `owner_id`, the user
+/// the connection is signed in as, since event data never says whose rows it's for.
 pub trait EventDb {
     type Error;
 
+    fn begin_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn commit_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn rollback_transaction(&mut self) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Holds a lock on the user's changes until the transaction ends, so rows are committed in
+    /// the order their sync cursors were handed out and a lazy sync can't skip past one.
+    fn lock_user_changes(&mut self, user_id: i32) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// The server sequence number of an event the user already sent, if any.
+    fn find_event_sequence(
+        &mut self,
+        user_id: i32,
+        event_id: &str,
+    ) -> impl Future<Output = Result<Option<i64>, Self::Error>>;
+
+    /// Appends the event to the event log and returns its server sequence number.
+    fn record_event(
+        &mut self,
+        record: EventRecord,
+    ) -> impl Future<Output = Result<i64, Self::Error>>;
+
     fn handle_create_project(
         &mut self,
+        owner_id: i32,
         data: CreateProjectEventData,
     ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_update_project_title(
+        &mut self,
+        owner_id: i32,
+        data: UpdateProjectTitleEventData,
+        clock: &Hlc,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_set_project_completed(
+        &mut self,
+        owner_id: i32,
+        data: SetProjectCompletedEventData,
+        clock: &Hlc,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_delete_project(
+        &mut self,
+        owner_id: i32,
+        data: DeleteProjectEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_create_section(
+        &mut self,
+        owner_id: i32,
+        data: CreateSectionEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_rename_section(
+        &mut self,
+        owner_id: i32,
+        data: RenameSectionEventData,
+        clock: &Hlc,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_delete_section(
+        &mut self,
+        owner_id: i32,
+        data: DeleteSectionEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_create_section_item(
+        &mut self,
+        owner_id: i32,
+        data: CreateSectionItemEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_update_section_item_text(
+        &mut self,
+        owner_id: i32,
+        data: UpdateSectionItemTextEventData,
+        clock: &Hlc,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_set_section_item_completed(
+        &mut self,
+        owner_id: i32,
+        data: SetSectionItemCompletedEventData,
+        clock: &Hlc,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_delete_section_item(
+        &mut self,
+        owner_id: i32,
+        data: DeleteSectionItemEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_reorder_section_items(
+        &mut self,
+        owner_id: i32,
+        data: ReorderSectionItemsEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_add_section_item_note(
+        &mut self,
+        owner_id: i32,
+        data: AddSectionItemNoteEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_edit_section_item_note(
+        &mut self,
+        owner_id: i32,
+        data: EditSectionItemNoteEventData,
+        clock: &Hlc,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    fn handle_remove_section_item_note(
+        &mut self,
+        owner_id: i32,
+        data: RemoveSectionItemNoteEventData,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
 }
 
 pub struct EventDatabase<'a> {
@@ -26,17 +368,116 @@ impl<'a> EventDatabase<'a> {
     pub fn new(conn: &'a mut pg::AsyncPgConnection) -> Self {
         Self { conn }
     }
+
+    /// Hard-deletes rows tombstoned before `cutoff`, along with any rows under a purged
+    /// parent, and moves each affected user's sync horizon past them.
+    pub async fn purge_tombstones(
+        &mut self,
+        cutoff: chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), EventDbError> {
+        self.conn
+            .transaction::<_, EventDbError, _>(|conn| {
+                async move {
+                    // children first so no foreign key is left pointing at a purged row
+                    for delete in [
+                        PURGE_SECTION_ITEM_NOTES,
+                        PURGE_SECTION_ITEMS,
+                        PURGE_SECTIONS,
+                        PURGE_PROJECTS,
+                    ] {
+                        diesel::sql_query(purge_statement(delete))
+                            .bind::<diesel::sql_types::Timestamptz, _>(cutoff)
+                            .execute(conn)
+                            .await
+                            .context(DatabaseSnafu)?;
+                    }
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
 }
 
 impl<'a> EventDb for EventDatabase<'a> {
-    type Error = diesel::result::Error;
+    type Error = EventDbError;
 
-    async fn handle_create_project(&mut self, data: CreateProjectEventData) -> Result<(), Self::Error> {
+    async fn begin_transaction(&mut self) -> Result<(), Self::Error> {
+        AnsiTransactionManager::begin_transaction(self.conn)
+            .await
+            .context(DatabaseSnafu)
+    }
+
+    async fn commit_transaction(&mut self) -> Result<(), Self::Error> {
+        AnsiTransactionManager::commit_transaction(self.conn)
+            .await
+            .context(DatabaseSnafu)
+    }
+
+    async fn rollback_transaction(&mut self) -> Result<(), Self::Error> {
+        AnsiTransactionManager::rollback_transaction(self.conn)
+            .await
+            .context(DatabaseSnafu)
+    }
+
+    async fn lock_user_changes(&mut self, user_id: i32) -> Result<(), Self::Error> {
+        diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+            .bind::<diesel::sql_types::BigInt, _>(i64::from(user_id))
+            .execute(self.conn)
+            .await
+            .context(DatabaseSnafu)?;
+
+        Ok(())
+    }
+
+    async fn find_event_sequence(
+        &mut self,
+        user_id: i32,
+        event_id: &str,
+    ) -> Result<Option<i64>, Self::Error> {
+        use app_db::schema::events::dsl;
+        use diesel::OptionalExtension;
+
+        dsl::events
+            .filter(dsl::user_id.eq(user_id))
+            .filter(dsl::event_id.eq(event_id))
+            .select(dsl::id)
+            .get_result(self.conn)
+            .await
+            .optional()
+            .context(DatabaseSnafu)
+    }
+
+    async fn record_event(&mut self, record: EventRecord) -> Result<i64, Self::Error> {
+        use app_db::schema::events::dsl::*;
+
+        diesel::insert_into(events)
+            .values((
+                event_id.eq(record.event_id),
+                user_id.eq(record.user_id),
+                event_type.eq(record.event_type),
+                payload.eq(record.payload),
+                schema_version.eq(record.schema_version),
+                hlc.eq(record.hlc),
+                received_at.eq(chrono::Utc::now()),
+            ))
+            .returning(id)
+            .get_result(self.conn)
+            .await
+            .context(DatabaseSnafu)
+    }
+
+    async fn handle_create_project(
+        &mut self,
+        owner_id: i32,
+        data: CreateProjectEventData,
+    ) -> Result<(), Self::Error> {
         use app_db::schema::projects::dsl::*;
 
         let count = diesel::insert_into(projects)
             .values((
-                user_id.eq(data.user_id),
+                user_id.eq(owner_id),
                 project_id.eq(data.project_id),
                 title.eq(data.title),
                 completed.eq(false),
@@ -44,96 +485,1317 @@ impl<'a> EventDb for EventDatabase<'a> {
                 updated_at.eq(chrono::Utc::now()),
             ))
             .execute(self.conn)
-            .await?;
+            .await
+            .context(DatabaseSnafu)?;
 
         debug_assert_eq!(count, 1);
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use diesel::QueryDsl;
-    use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
-    use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
-    use testcontainers_modules::{
-        postgres::{self, Postgres},
-        testcontainers::{ContainerAsync, runners::AsyncRunner},
-    };
-    pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("../app_db/migrations");
+    async fn handle_update_project_title(
+        &mut self,
+        owner_id: i32,
+        data: UpdateProjectTitleEventData,
+        clock: &Hlc,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::projects::dsl::*;
 
-    async fn start_postgres() -> (ContainerAsync<Postgres>, AsyncPgConnection) {
-        let postgres_instance_handle = postgres::Postgres::default().start().await.unwrap();
+        let clock = clock.encode();
+        let count = diesel::update(
+            projects
+                .filter(user_id.eq(owner_id))
+                .filter(project_id.eq(data.project_id))
+                .filter(is_deleted.eq(false))
+                .filter(title_clock.lt(&clock)),
+        )
+        .set((
+            title.eq(data.title),
+            title_clock.eq(&clock),
+            updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
 
-        let connection_string =
-            connection_string_from_postgres_instance(&postgres_instance_handle).await;
-        run_migrations(connection_string.clone());
+        let exists = diesel::select(diesel::dsl::exists(
+            projects
+                .filter(user_id.eq(owner_id))
+                .filter(project_id.eq(data.project_id)),
+        ))
+        .get_result(self.conn);
+        guarded_update(count, exists, "project", "project title").await
+    }
 
-        let conn = pg::AsyncPgConnection::establish(&connection_string)
-            .await
-            .unwrap();
-        (postgres_instance_handle, conn)
+    async fn handle_set_project_completed(
+        &mut self,
+        owner_id: i32,
+        data: SetProjectCompletedEventData,
+        clock: &Hlc,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::projects::dsl::*;
+
+        let clock = clock.encode();
+        let count = diesel::update(
+            projects
+                .filter(user_id.eq(owner_id))
+                .filter(project_id.eq(data.project_id))
+                .filter(is_deleted.eq(false))
+                .filter(completed_clock.lt(&clock)),
+        )
+        .set((
+            completed.eq(data.completed),
+            completed_clock.eq(&clock),
+            updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        let exists = diesel::select(diesel::dsl::exists(
+            projects
+                .filter(user_id.eq(owner_id))
+                .filter(project_id.eq(data.project_id)),
+        ))
+        .get_result(self.conn);
+        guarded_update(count, exists, "project", "project completion").await
     }
 
-    async fn connection_string_from_postgres_instance(
-        container: &ContainerAsync<Postgres>,
-    ) -> String {
-        format!(
-            "postgres://postgres:postgres@{}:{}/postgres",
-            container.get_host().await.unwrap(),
-            container.get_host_port_ipv4(5432).await.unwrap()
+    async fn handle_delete_project(
+        &mut self,
+        owner_id: i32,
+        data: DeleteProjectEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::projects::dsl::*;
+
+        // projects are only flagged so other devices can still sync the delete
+        let now = chrono::Utc::now();
+        let count = diesel::update(
+            projects
+                .filter(user_id.eq(owner_id))
+                .filter(project_id.eq(data.project_id)),
         )
+        .set((is_deleted.eq(true), deleted_at.eq(now), updated_at.eq(now)))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        if count == 0 {
+            return NotFoundSnafu { entity: "project" }.fail();
+        }
+
+        Ok(())
     }
 
-    fn run_migrations(connection_string: String) {
-        use diesel::PgConnection;
-        use diesel::prelude::*;
-        let mut sync_conn = PgConnection::establish(&connection_string).unwrap();
-        sync_conn.run_pending_migrations(MIGRATIONS).unwrap();
+    async fn handle_create_section(
+        &mut self,
+        owner_id: i32,
+        data: CreateSectionEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::{projects, sections::dsl::*};
+
+        let now = chrono::Utc::now();
+        let parent_id = data.project_id;
+        let inserted = self
+            .conn
+            .transaction(|conn| {
+                async move {
+                    diesel::insert_into(sections)
+                        .values((
+                            user_id.eq(owner_id),
+                            project_id.eq(data.project_id),
+                            section_id.eq(data.section_id),
+                            name.eq(data.name),
+                            is_deleted.eq(false),
+                            created_at.eq(now),
+                            updated_at.eq(now),
+                        ))
+                        .execute(conn)
+                        .await
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        let owned_elsewhere = diesel::select(diesel::dsl::exists(
+            projects::table.filter(projects::project_id.eq(parent_id)),
+        ))
+        .get_result(self.conn);
+        insert_under_parent(inserted, owned_elsewhere, "project").await
     }
 
-    async fn seed_one_project(conn: &mut AsyncPgConnection) {
-        use api::{Email, UserInput};
-        use app_db::schema::users::dsl::*;
+    async fn handle_rename_section(
+        &mut self,
+        owner_id: i32,
+        data: RenameSectionEventData,
+        clock: &Hlc,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::sections::dsl::*;
+
+        let clock = clock.encode();
+        let count = diesel::update(
+            sections
+                .filter(user_id.eq(owner_id))
+                .filter(section_id.eq(data.section_id))
+                .filter(is_deleted.eq(false))
+                .filter(name_clock.lt(&clock)),
+        )
+        .set((
+            name.eq(data.name),
+            name_clock.eq(&clock),
+            updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        let exists = diesel::select(diesel::dsl::exists(
+            sections
+                .filter(user_id.eq(owner_id))
+                .filter(section_id.eq(data.section_id)),
+        ))
+        .get_result(self.conn);
+        guarded_update(count, exists, "section", "section name").await
+    }
+
+    async fn handle_delete_section(
+        &mut self,
+        owner_id: i32,
+        data: DeleteSectionEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::sections::dsl::*;
+
         let now = chrono::Utc::now();
-        let user_input = UserInput::new(
-            Email::new("test@example.com").unwrap(),
-            "password".to_string(),
-            now,
-            now,
-        );
+        let count = diesel::update(
+            sections
+                .filter(user_id.eq(owner_id))
+                .filter(section_id.eq(data.section_id)),
+        )
+        .set((is_deleted.eq(true), deleted_at.eq(now), updated_at.eq(now)))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
 
-        diesel::insert_into(users)
-            .values(user_input)
-            .execute(conn)
-            .await
-            .unwrap();
+        if count == 0 {
+            return NotFoundSnafu { entity: "section" }.fail();
+        }
+
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn add_project() {
-        let (_postgres_instance_handle, mut conn) = start_postgres().await;
-        seed_one_project(&mut conn).await;
+    async fn handle_create_section_item(
+        &mut self,
+        owner_id: i32,
+        data: CreateSectionItemEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::{section_items::dsl::*, sections};
 
-        let mut db = EventDatabase::new(&mut conn);
+        let now = chrono::Utc::now();
+        let parent_id = data.section_id;
+        let inserted = self
+            .conn
+            .transaction(|conn| {
+                async move {
+                    diesel::insert_into(section_items)
+                        .values((
+                            user_id.eq(owner_id),
+                            section_id.eq(data.section_id),
+                            item_id.eq(data.item_id),
+                            text.eq(data.text),
+                            is_complete.eq(false),
+                            item_order.eq(data.order),
+                            is_deleted.eq(false),
+                            created_at.eq(now),
+                            updated_at.eq(now),
+                        ))
+                        .execute(conn)
+                        .await
+                }
+                .scope_boxed()
+            })
+            .await;
 
-        let data = CreateProjectEventData {
-            user_id: 1,
-            project_id: 1,
-            title: "Test Project".to_string(),
-        };
-        db.handle_create_project(data).await.unwrap();
+        let owned_elsewhere = diesel::select(diesel::dsl::exists(
+            sections::table.filter(sections::section_id.eq(parent_id)),
+        ))
+        .get_result(self.conn);
+        insert_under_parent(inserted, owned_elsewhere, "section").await
+    }
 
-        use app_db::schema::projects::dsl::*;
+    async fn handle_update_section_item_text(
+        &mut self,
+        owner_id: i32,
+        data: UpdateSectionItemTextEventData,
+        clock: &Hlc,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::section_items::dsl::*;
 
-        let result: String = projects
-            .filter(title.eq("Test Project"))
-            .select(title)
-            .get_result(&mut conn)
-            .await
-            .unwrap();
-        assert_eq!(result, "Test Project");
+        let clock = clock.encode();
+        let count = diesel::update(
+            section_items
+                .filter(user_id.eq(owner_id))
+                .filter(item_id.eq(data.item_id))
+                .filter(is_deleted.eq(false))
+                .filter(text_clock.lt(&clock)),
+        )
+        .set((
+            text.eq(data.text),
+            text_clock.eq(&clock),
+            updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        let exists = diesel::select(diesel::dsl::exists(
+            section_items
+                .filter(user_id.eq(owner_id))
+                .filter(item_id.eq(data.item_id)),
+        ))
+        .get_result(self.conn);
+        guarded_update(count, exists, "section item", "section item text").await
+    }
+
+    async fn handle_set_section_item_completed(
+        &mut self,
+        owner_id: i32,
+        data: SetSectionItemCompletedEventData,
+        clock: &Hlc,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::section_items::dsl::*;
+
+        let clock = clock.encode();
+        let count = diesel::update(
+            section_items
+                .filter(user_id.eq(owner_id))
+                .filter(item_id.eq(data.item_id))
+                .filter(is_deleted.eq(false))
+                .filter(is_complete_clock.lt(&clock)),
+        )
+        .set((
+            is_complete.eq(data.is_complete),
+            is_complete_clock.eq(&clock),
+            updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        let exists = diesel::select(diesel::dsl::exists(
+            section_items
+                .filter(user_id.eq(owner_id))
+                .filter(item_id.eq(data.item_id)),
+        ))
+        .get_result(self.conn);
+        guarded_update(count, exists, "section item", "section item completion").await
+    }
+
+    async fn handle_delete_section_item(
+        &mut self,
+        owner_id: i32,
+        data: DeleteSectionItemEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::{section_item_notes, section_items};
+
+        // an item's notes are tombstoned along with it, the same as on the client
+        self.conn
+            .transaction::<_, EventDbError, _>(|conn| {
+                async move {
+                    let now = chrono::Utc::now();
+                    let count = diesel::update(
+                        section_items::table
+                            .filter(section_items::user_id.eq(owner_id))
+                            .filter(section_items::item_id.eq(data.item_id)),
+                    )
+                    .set((
+                        section_items::is_deleted.eq(true),
+                        section_items::deleted_at.eq(now),
+                        section_items::updated_at.eq(now),
+                    ))
+                    .execute(conn)
+                    .await
+                    .context(DatabaseSnafu)?;
+
+                    if count == 0 {
+                        return NotFoundSnafu {
+                            entity: "section item",
+                        }
+                        .fail();
+                    }
+
+                    diesel::update(
+                        section_item_notes::table
+                            .filter(section_item_notes::user_id.eq(owner_id))
+                            .filter(section_item_notes::item_id.eq(data.item_id))
+                            .filter(section_item_notes::is_deleted.eq(false)),
+                    )
+                    .set((
+                        section_item_notes::is_deleted.eq(true),
+                        section_item_notes::deleted_at.eq(now),
+                        section_item_notes::updated_at.eq(now),
+                    ))
+                    .execute(conn)
+                    .await
+                    .context(DatabaseSnafu)?;
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn handle_reorder_section_items(
+        &mut self,
+        owner_id: i32,
+        data: ReorderSectionItemsEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::section_items::dsl::*;
+
+        // every order value in the section is rewritten together or not at all
+        self.conn
+            .transaction::<_, EventDbError, _>(|conn| {
+                async move {
+                    let mut current_item_ids: Vec<i32> = section_items
+                        .filter(user_id.eq(owner_id))
+                        .filter(section_id.eq(data.section_id))
+                        .filter(is_deleted.eq(false))
+                        .select(item_id)
+                        .for_update()
+                        .load(conn)
+                        .await
+                        .context(DatabaseSnafu)?;
+                    current_item_ids.sort_unstable();
+
+                    let mut reordered_item_ids = data.item_ids.clone();
+                    reordered_item_ids.sort_unstable();
+                    if current_item_ids != reordered_item_ids {
+                        return ReorderMismatchSnafu {
+                            section_id: data.section_id,
+                        }
+                        .fail();
+                    }
+
+                    let now = chrono::Utc::now();
+                    for (order, reordered_item_id) in data.item_ids.iter().enumerate() {
+                        diesel::update(
+                            section_items
+                                .filter(user_id.eq(owner_id))
+                                .filter(item_id.eq(reordered_item_id)),
+                        )
+                        .set((item_order.eq(order as i32), updated_at.eq(now)))
+                        .execute(conn)
+                        .await
+                        .context(DatabaseSnafu)?;
+                    }
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await
+    }
+
+    async fn handle_add_section_item_note(
+        &mut self,
+        owner_id: i32,
+        data: AddSectionItemNoteEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::{section_item_notes::dsl::*, section_items};
+
+        let now = chrono::Utc::now();
+        let parent_id = data.item_id;
+        let inserted = self
+            .conn
+            .transaction(|conn| {
+                async move {
+                    diesel::insert_into(section_item_notes)
+                        .values((
+                            user_id.eq(owner_id),
+                            item_id.eq(data.item_id),
+                            note_id.eq(data.note_id),
+                            text.eq(data.text),
+                            is_deleted.eq(false),
+                            created_at.eq(now),
+                            updated_at.eq(now),
+                        ))
+                        .execute(conn)
+                        .await
+                }
+                .scope_boxed()
+            })
+            .await;
+
+        let owned_elsewhere = diesel::select(diesel::dsl::exists(
+            section_items::table.filter(section_items::item_id.eq(parent_id)),
+        ))
+        .get_result(self.conn);
+        insert_under_parent(inserted, owned_elsewhere, "section item").await
+    }
+
+    async fn handle_edit_section_item_note(
+        &mut self,
+        owner_id: i32,
+        data: EditSectionItemNoteEventData,
+        clock: &Hlc,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::section_item_notes::dsl::*;
+
+        let clock = clock.encode();
+        let count = diesel::update(
+            section_item_notes
+                .filter(user_id.eq(owner_id))
+                .filter(note_id.eq(data.note_id))
+                .filter(is_deleted.eq(false))
+                .filter(text_clock.lt(&clock)),
+        )
+        .set((
+            text.eq(data.text),
+            text_clock.eq(&clock),
+            updated_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        let exists = diesel::select(diesel::dsl::exists(
+            section_item_notes
+                .filter(user_id.eq(owner_id))
+                .filter(note_id.eq(data.note_id)),
+        ))
+        .get_result(self.conn);
+        guarded_update(count, exists, "section item note", "section item note text").await
+    }
+
+    async fn handle_remove_section_item_note(
+        &mut self,
+        owner_id: i32,
+        data: RemoveSectionItemNoteEventData,
+    ) -> Result<(), Self::Error> {
+        use app_db::schema::section_item_notes::dsl::*;
+
+        let now = chrono::Utc::now();
+        let count = diesel::update(
+            section_item_notes
+                .filter(user_id.eq(owner_id))
+                .filter(note_id.eq(data.note_id)),
+        )
+        .set((is_deleted.eq(true), deleted_at.eq(now), updated_at.eq(now)))
+        .execute(self.conn)
+        .await
+        .context(DatabaseSnafu)?;
+
+        if count == 0 {
+            return NotFoundSnafu {
+                entity: "section item note",
+            }
+            .fail();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::QueryDsl;
+    use diesel_async::{AsyncConnection, AsyncPgConnection, RunQueryDsl};
+    use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+    use testcontainers_modules::{
+        postgres::{self, Postgres},
+        testcontainers::{ContainerAsync, runners::AsyncRunner},
+    };
+    pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("../app_db/migrations");
+
+    async fn start_postgres() -> (ContainerAsync<Postgres>, AsyncPgConnection) {
+        let postgres_instance_handle = postgres::Postgres::default().start().await.unwrap();
+
+        let connection_string =
+            connection_string_from_postgres_instance(&postgres_instance_handle).await;
+        run_migrations(connection_string.clone());
+
+        let conn = pg::AsyncPgConnection::establish(&connection_string)
+            .await
+            .unwrap();
+        (postgres_instance_handle, conn)
+    }
+
+    async fn connection_string_from_postgres_instance(
+        container: &ContainerAsync<Postgres>,
+    ) -> String {
+        format!(
+            "postgres://postgres:postgres@{}:{}/postgres",
+            container.get_host().await.unwrap(),
+            container.get_host_port_ipv4(5432).await.unwrap()
+        )
+    }
+
+    fn run_migrations(connection_string: String) {
+        use diesel::PgConnection;
+        use diesel::prelude::*;
+        let mut sync_conn = PgConnection::establish(&connection_string).unwrap();
+        sync_conn.run_pending_migrations(MIGRATIONS).unwrap();
+    }
+
+    async fn seed_one_project(conn: &mut AsyncPgConnection) {
+        use api::{Email, UserInput};
+        use app_db::schema::users::dsl::*;
+        let now = chrono::Utc::now();
+        let user_input = UserInput::new(
+            Email::new("test@example.com").unwrap(),
+            "password".to_string(),
+            now,
+            now,
+        );
+
+        diesel::insert_into(users)
+            .values(user_input)
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    /// How far down the project tree a test's rows go, each level including the ones above
+    /// it. Items are numbered from 1 and the note is on item 1.
+    #[derive(Clone, Copy)]
+    enum Seed {
+        User,
+        Project,
+        Section,
+        Items(i32),
+        ItemsWithNote(i32),
+    }
+
+    /// A database of its own for one test, with user 1 and the rows its [`Seed`] asked for.
+    struct TestDb {
+        _postgres_instance_handle: ContainerAsync<Postgres>,
+        conn: AsyncPgConnection,
+    }
+
+    impl TestDb {
+        fn db(&mut self) -> EventDatabase<'_> {
+            EventDatabase::new(&mut self.conn)
+        }
+    }
+
+    async fn fixture(seed: Seed) -> TestDb {
+        let (postgres_instance_handle, mut conn) = start_postgres().await;
+        seed_one_project(&mut conn).await;
+
+        let mut db = EventDatabase::new(&mut conn);
+        if !matches!(seed, Seed::User) {
+            create_test_project(&mut db).await;
+        }
+        if !matches!(seed, Seed::User | Seed::Project) {
+            create_test_section(&mut db).await;
+        }
+        if let Seed::Items(count) | Seed::ItemsWithNote(count) = seed {
+            create_test_section_items(&mut db, count).await;
+        }
+        if let Seed::ItemsWithNote(_) = seed {
+            create_test_note(&mut db).await;
+        }
+
+        TestDb {
+            _postgres_instance_handle: postgres_instance_handle,
+            conn,
+        }
+    }
+
+    #[tokio::test]
+    async fn add_project() {
+        let mut test = fixture(Seed::User).await;
+
+        let data = CreateProjectEventData {
+            project_id: 1,
+            title: "Test Project".to_string(),
+        };
+        test.db().handle_create_project(1, data).await.unwrap();
+
+        use app_db::schema::projects::dsl::*;
+
+        let result: String = projects
+            .filter(title.eq("Test Project"))
+            .select(title)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, "Test Project");
+    }
+
+    async fn create_test_project(db: &mut EventDatabase<'_>) {
+        let data = CreateProjectEventData {
+            project_id: 1,
+            title: "Test Project".to_string(),
+        };
+        db.handle_create_project(1, data).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn update_project_title() {
+        let mut test = fixture(Seed::Project).await;
+
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Renamed Project".to_string(),
+        };
+        test.db()
+            .handle_update_project_title(1, data, &test_clock(1))
+            .await
+            .unwrap();
+
+        use app_db::schema::projects::dsl::*;
+
+        let result: String = projects
+            .filter(project_id.eq(1))
+            .select(title)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, "Renamed Project");
+    }
+
+    fn test_clock(wall_time: u64) -> Hlc {
+        Hlc::new(wall_time, 0, "test-device")
+    }
+
+    #[tokio::test]
+    async fn concurrent_title_edits_keep_latest_clock() {
+        let mut test = fixture(Seed::Project).await;
+        let mut db = test.db();
+
+        // the later edit reaches the server first
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Edited on iPad".to_string(),
+        };
+        db.handle_update_project_title(1, data, &Hlc::new(2000, 0, "ipad"))
+            .await
+            .unwrap();
+
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Edited on iPhone".to_string(),
+        };
+        let result = db
+            .handle_update_project_title(1, data, &Hlc::new(1000, 0, "iphone"))
+            .await;
+        assert!(matches!(result, Err(EventDbError::Superseded { .. })));
+
+        // other fields keep their own clocks
+        let data = SetProjectCompletedEventData {
+            project_id: 1,
+            completed: true,
+        };
+        db.handle_set_project_completed(1, data, &Hlc::new(1000, 0, "iphone"))
+            .await
+            .unwrap();
+
+        use app_db::schema::projects::dsl::*;
+
+        let result: (String, bool) = projects
+            .filter(project_id.eq(1))
+            .select((title, completed))
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, ("Edited on iPad".to_string(), true));
+    }
+
+    #[tokio::test]
+    async fn tied_title_edits_resolve_like_hlc_ordering() {
+        let mut test = fixture(Seed::Project).await;
+        let mut db = test.db();
+
+        // same wall time and counter, so the node decides. "B" sorts before "a" byte for byte
+        // but after it in most locale collations
+        let winner = Hlc::new(1000, 0, "a");
+        let loser = Hlc::new(1000, 0, "B");
+        assert!(loser < winner);
+
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Edited on a".to_string(),
+        };
+        db.handle_update_project_title(1, data, &winner)
+            .await
+            .unwrap();
+
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Edited on B".to_string(),
+        };
+        let result = db.handle_update_project_title(1, data, &loser).await;
+        assert!(matches!(result, Err(EventDbError::Superseded { .. })));
+    }
+
+    #[tokio::test]
+    async fn server_stamped_edits_in_the_same_millisecond_both_apply() {
+        let mut test = fixture(Seed::Project).await;
+        let mut db = test.db();
+        let clock = ServerClock::new();
+
+        for new_title in ["Quilt", "Baby quilt"] {
+            let data = UpdateProjectTitleEventData {
+                project_id: 1,
+                title: new_title.to_string(),
+            };
+            db.handle_update_project_title(1, data, &clock.now())
+                .await
+                .unwrap();
+        }
+
+        use app_db::schema::projects::dsl::*;
+
+        let result: String = projects
+            .filter(project_id.eq(1))
+            .select(title)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, "Baby quilt");
+    }
+
+    #[tokio::test]
+    async fn update_missing_project_title() {
+        let mut test = fixture(Seed::User).await;
+
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Renamed Project".to_string(),
+        };
+        let result = test
+            .db()
+            .handle_update_project_title(1, data, &test_clock(1))
+            .await;
+        assert!(matches!(result, Err(EventDbError::NotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn set_project_completed() {
+        let mut test = fixture(Seed::Project).await;
+        let mut db = test.db();
+
+        let data = SetProjectCompletedEventData {
+            project_id: 1,
+            completed: true,
+        };
+        db.handle_set_project_completed(1, data, &test_clock(1))
+            .await
+            .unwrap();
+
+        let data = SetProjectCompletedEventData {
+            project_id: 1,
+            completed: false,
+        };
+        db.handle_set_project_completed(1, data, &test_clock(2))
+            .await
+            .unwrap();
+
+        use app_db::schema::projects::dsl::*;
+
+        let result: bool = projects
+            .filter(project_id.eq(1))
+            .select(completed)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert!(!result);
+    }
+
+    #[tokio::test]
+    async fn delete_project() {
+        let mut test = fixture(Seed::Project).await;
+
+        let data = DeleteProjectEventData { project_id: 1 };
+        test.db().handle_delete_project(1, data).await.unwrap();
+
+        use app_db::schema::projects::dsl::*;
+
+        let result: bool = projects
+            .filter(project_id.eq(1))
+            .select(is_deleted)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert!(result);
+    }
+
+    async fn create_test_section(db: &mut EventDatabase<'_>) {
+        let data = CreateSectionEventData {
+            project_id: 1,
+            section_id: 1,
+            name: "Test Section".to_string(),
+        };
+        db.handle_create_section(1, data).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn add_section() {
+        let mut test = fixture(Seed::Section).await;
+
+        use app_db::schema::sections::dsl::*;
+
+        let result: (i32, String) = sections
+            .filter(section_id.eq(1))
+            .select((project_id, name))
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, (1, "Test Section".to_string()));
+    }
+
+    #[tokio::test]
+    async fn add_section_to_missing_project() {
+        let mut test = fixture(Seed::User).await;
+
+        let data = CreateSectionEventData {
+            project_id: 1,
+            section_id: 1,
+            name: "Test Section".to_string(),
+        };
+        let result = test.db().handle_create_section(1, data).await;
+        assert!(matches!(
+            result,
+            Err(EventDbError::Database {
+                source: diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::ForeignKeyViolation,
+                    _
+                ),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn add_section_to_another_users_project() {
+        let mut test = fixture(Seed::User).await;
+
+        {
+            use api::{Email, UserInput};
+            use app_db::schema::users::dsl::*;
+            let now = chrono::Utc::now();
+            let other_user = UserInput::new(
+                Email::new("other@example.com").unwrap(),
+                "password".to_string(),
+                now,
+                now,
+            );
+            diesel::insert_into(users)
+                .values(other_user)
+                .execute(&mut test.conn)
+                .await
+                .unwrap();
+        }
+
+        let mut db = test.db();
+        let data = CreateProjectEventData {
+            project_id: 5,
+            title: "Their Project".to_string(),
+        };
+        db.handle_create_project(2, data).await.unwrap();
+
+        let data = CreateSectionEventData {
+            project_id: 5,
+            section_id: 1,
+            name: "Test Section".to_string(),
+        };
+        let result = db.handle_create_section(1, data).await;
+        assert!(matches!(
+            result,
+            Err(EventDbError::NotOwned { entity: "project" })
+        ));
+
+        use app_db::schema::sections::dsl::*;
+
+        let count: i64 = sections.count().get_result(&mut test.conn).await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn rename_section() {
+        let mut test = fixture(Seed::Section).await;
+
+        let data = RenameSectionEventData {
+            section_id: 1,
+            name: "Renamed Section".to_string(),
+        };
+        test.db()
+            .handle_rename_section(1, data, &test_clock(1))
+            .await
+            .unwrap();
+
+        use app_db::schema::sections::dsl::*;
+
+        let result: String = sections
+            .filter(section_id.eq(1))
+            .select(name)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, "Renamed Section");
+    }
+
+    #[tokio::test]
+    async fn delete_section() {
+        let mut test = fixture(Seed::Section).await;
+
+        let data = DeleteSectionEventData { section_id: 1 };
+        test.db().handle_delete_section(1, data).await.unwrap();
+
+        use app_db::schema::sections::dsl::*;
+
+        let result: bool = sections
+            .filter(section_id.eq(1))
+            .select(is_deleted)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert!(result);
+    }
+
+    async fn create_test_section_items(db: &mut EventDatabase<'_>, count: i32) {
+        for id in 1..=count {
+            let data = CreateSectionItemEventData {
+                section_id: 1,
+                item_id: id,
+                text: format!("Item {}", id),
+                order: id - 1,
+            };
+            db.handle_create_section_item(1, data).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn add_section_item() {
+        let mut test = fixture(Seed::Items(1)).await;
+        let mut db = test.db();
+
+        let data = UpdateSectionItemTextEventData {
+            item_id: 1,
+            text: "Cut fabric".to_string(),
+        };
+        db.handle_update_section_item_text(1, data, &test_clock(1))
+            .await
+            .unwrap();
+
+        let data = SetSectionItemCompletedEventData {
+            item_id: 1,
+            is_complete: true,
+        };
+        db.handle_set_section_item_completed(1, data, &test_clock(1))
+            .await
+            .unwrap();
+
+        use app_db::schema::section_items::dsl::*;
+
+        let result: (String, bool) = section_items
+            .filter(item_id.eq(1))
+            .select((text, is_complete))
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, ("Cut fabric".to_string(), true));
+    }
+
+    #[tokio::test]
+    async fn reorder_section_items() {
+        let mut test = fixture(Seed::Items(3)).await;
+
+        let data = ReorderSectionItemsEventData {
+            section_id: 1,
+            item_ids: vec![3, 1, 2],
+        };
+        test.db()
+            .handle_reorder_section_items(1, data)
+            .await
+            .unwrap();
+
+        use app_db::schema::section_items::dsl::*;
+
+        let result: Vec<i32> = section_items
+            .filter(section_id.eq(1))
+            .order(item_order.asc())
+            .select(item_id)
+            .load(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, vec![3, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn reorder_section_items_with_missing_item() {
+        let mut test = fixture(Seed::Items(3)).await;
+
+        let data = ReorderSectionItemsEventData {
+            section_id: 1,
+            item_ids: vec![3, 1],
+        };
+        let result = test.db().handle_reorder_section_items(1, data).await;
+        assert!(matches!(
+            result,
+            Err(EventDbError::ReorderMismatch { section_id: 1 })
+        ));
+
+        use app_db::schema::section_items::dsl::*;
+
+        let result: Vec<i32> = section_items
+            .filter(section_id.eq(1))
+            .order(item_order.asc())
+            .select(item_id)
+            .load(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn delete_section_item() {
+        let mut test = fixture(Seed::Items(1)).await;
+
+        let data = DeleteSectionItemEventData { item_id: 1 };
+        test.db().handle_delete_section_item(1, data).await.unwrap();
+
+        use app_db::schema::section_items::dsl::*;
+
+        let result: bool = section_items
+            .filter(item_id.eq(1))
+            .select(is_deleted)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert!(result);
+    }
+
+    #[tokio::test]
+    async fn edit_to_deleted_section_item_is_superseded() {
+        let mut test = fixture(Seed::Items(1)).await;
+        let mut db = test.db();
+
+        let data = DeleteSectionItemEventData { item_id: 1 };
+        db.handle_delete_section_item(1, data).await.unwrap();
+
+        let data = UpdateSectionItemTextEventData {
+            item_id: 1,
+            text: "Cut fabric".to_string(),
+        };
+        let result = db
+            .handle_update_section_item_text(1, data, &test_clock(1))
+            .await;
+        assert!(matches!(result, Err(EventDbError::Superseded { .. })));
+
+        use app_db::schema::section_items::dsl::*;
+
+        let (result_text, result_deleted): (String, bool) = section_items
+            .filter(item_id.eq(1))
+            .select((text, is_deleted))
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_ne!(result_text, "Cut fabric");
+        assert!(result_deleted);
+    }
+
+    async fn create_test_note(db: &mut EventDatabase<'_>) {
+        let data = AddSectionItemNoteEventData {
+            item_id: 1,
+            note_id: 1,
+            text: "Use the walking foot".to_string(),
+        };
+        db.handle_add_section_item_note(1, data).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn edit_section_item_note() {
+        let mut test = fixture(Seed::ItemsWithNote(1)).await;
+
+        let data = EditSectionItemNoteEventData {
+            note_id: 1,
+            text: "Use a size 90 needle".to_string(),
+        };
+        test.db()
+            .handle_edit_section_item_note(1, data, &test_clock(1))
+            .await
+            .unwrap();
+
+        use app_db::schema::section_item_notes::dsl::*;
+
+        let result: String = section_item_notes
+            .filter(note_id.eq(1))
+            .select(text)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, "Use a size 90 needle");
+    }
+
+    #[tokio::test]
+    async fn delete_section_item_removes_notes() {
+        let mut test = fixture(Seed::ItemsWithNote(1)).await;
+
+        let data = DeleteSectionItemEventData { item_id: 1 };
+        test.db().handle_delete_section_item(1, data).await.unwrap();
+
+        use app_db::schema::section_item_notes::dsl::*;
+
+        let result: bool = section_item_notes
+            .filter(note_id.eq(1))
+            .select(is_deleted)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert!(result);
+    }
+
+    #[tokio::test]
+    async fn purge_deleted_project() {
+        let mut test = fixture(Seed::ItemsWithNote(2)).await;
+        let mut db = test.db();
+
+        let data = DeleteProjectEventData { project_id: 1 };
+        db.handle_delete_project(1, data).await.unwrap();
+
+        // still within the retention horizon
+        let an_hour_ago = chrono::Utc::now() - chrono::Duration::hours(1);
+        db.purge_tombstones(an_hour_ago).await.unwrap();
+
+        use app_db::schema::{projects, section_items, sync_horizons};
+
+        let count: i64 = projects::table
+            .count()
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+
+        test.db()
+            .purge_tombstones(chrono::Utc::now())
+            .await
+            .unwrap();
+
+        let count: i64 = projects::table
+            .count()
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+        let count: i64 = section_items::table
+            .count()
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let purged_cursor: i64 = sync_horizons::table
+            .filter(sync_horizons::user_id.eq(1))
+            .select(sync_horizons::purged_cursor)
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert!(purged_cursor > 0);
+    }
+
+    #[tokio::test]
+    async fn purge_keeps_live_rows() {
+        let mut test = fixture(Seed::Items(2)).await;
+        let mut db = test.db();
+
+        let data = DeleteSectionItemEventData { item_id: 1 };
+        db.handle_delete_section_item(1, data).await.unwrap();
+        db.purge_tombstones(chrono::Utc::now()).await.unwrap();
+
+        use app_db::schema::section_items::dsl::*;
+
+        let remaining: Vec<i32> = section_items
+            .select(item_id)
+            .load(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(remaining, vec![2]);
+    }
+
+    fn test_event_record() -> EventRecord {
+        EventRecord {
+            event_id: "7d4c9a52-5f7b-4d5e-9d57-1b0f2f3c8e21".to_string(),
+            user_id: 1,
+            event_type: "CreateProject".to_string(),
+            payload: serde_json::json!({
+                "type": "CreateProject",
+                "project_id": 1,
+                "title": "Test Project",
+            }),
+            schema_version: 1,
+            hlc: test_clock(1).encode(),
+        }
+    }
+
+    #[tokio::test]
+    async fn record_event_with_projection() {
+        let mut test = fixture(Seed::User).await;
+        let mut db = test.db();
+
+        db.begin_transaction().await.unwrap();
+        let sequence = db.record_event(test_event_record()).await.unwrap();
+        create_test_project(&mut db).await;
+        db.commit_transaction().await.unwrap();
+
+        use app_db::schema::events::dsl::*;
+
+        let result: (i64, String) = events
+            .select((id, event_type))
+            .get_result(&mut test.conn)
+            .await
+            .unwrap();
+        assert_eq!(result, (sequence, "CreateProject".to_string()));
+    }
+
+    #[tokio::test]
+    async fn find_recorded_event() {
+        let mut test = fixture(Seed::User).await;
+        let mut db = test.db();
+
+        let record = test_event_record();
+        let event_id = record.event_id.clone();
+        let sequence = db.record_event(record).await.unwrap();
+
+        let found = db.find_event_sequence(1, &event_id).await.unwrap();
+        assert_eq!(found, Some(sequence));
+
+        let result = db.record_event(test_event_record()).await;
+        assert!(matches!(
+            result,
+            Err(EventDbError::Database {
+                source: diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation,
+                    _
+                ),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rolled_back_event_is_not_recorded() {
+        let mut test = fixture(Seed::User).await;
+        let mut db = test.db();
+
+        db.begin_transaction().await.unwrap();
+        db.record_event(test_event_record()).await.unwrap();
+        let data = UpdateProjectTitleEventData {
+            project_id: 1,
+            title: "Renamed Project".to_string(),
+        };
+        assert!(
+            db.handle_update_project_title(1, data, &test_clock(1))
+                .await
+                .is_err()
+        );
+        db.rollback_transaction().await.unwrap();
+
+        use app_db::schema::events::dsl::*;
+
+        let count: i64 = events.count().get_result(&mut test.conn).await.unwrap();
+        assert_eq!(count, 0);
     }
 }
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX sessions_user_id_idx;
+
+ALTER TABLE sessions DROP COLUMN user_id;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE sessions ADD COLUMN user_id INTEGER;
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
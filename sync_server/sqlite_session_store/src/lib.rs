@@ -18,6 +18,10 @@ use serde::Serialize;
 
 type SessionState = std::collections::HashMap<String, String>;
 
+/// The session key the app stores the logged in user under. It's copied into its own column
+/// so every session a user has can be found at once.
+const USER_ID_KEY: &str = "user_id";
+
 #[derive(Clone)]
 pub struct SqliteSessionStore {
     conn: Arc<Mutex<SyncConnectionWrapper<SqliteConnection>>>,
@@ -31,6 +35,7 @@ pub struct StoreSession {
     id: String,
     data: Vec<u8>,
     expires: NaiveDateTime,
+    user_id: Option<i32>,
 }
 
 impl SqliteSessionStore {
@@ -57,6 +62,30 @@ impl SqliteSessionStore {
         }
     }
 
+    /// Deletes every session belonging to the user, logging them out on all of their devices.
+    /// Returns how many sessions were deleted.
+    pub async fn delete_user_sessions(&self, user: i32) -> Result<usize, anyhow::Error> {
+        use crate::schema::sessions::*;
+
+        let mut conn = self.conn.lock().await;
+        let result = diesel::delete(table.filter(user_id.eq(user)))
+            .execute(&mut conn)
+            .await;
+
+        // TODO: add logging
+        match result {
+            Ok(count) => Ok(count),
+            Err(err) => Err(anyhow!("failed to delete user's sessions").context(err)),
+        }
+    }
+
+    /// Values in the session state are json, so the user id is stored as its json encoding.
+    fn session_user_id(session_state: &SessionState) -> Option<i32> {
+        session_state
+            .get(USER_ID_KEY)
+            .and_then(|value| serde_json::from_str(value).ok())
+    }
+
     fn calculate_expires(
         now: &NaiveDateTime,
         ttl: &actix_web::cookie::time::Duration,
@@ -81,6 +110,8 @@ impl SessionStore for SqliteSessionStore {
                 .await
             {
                 Ok(session) => session,
+                // a session that was logged out, or logged out everywhere, is just gone
+                Err(diesel::result::Error::NotFound) => return Ok(None),
                 Err(err) => {
                     return Err(LoadError::Other(
                         anyhow!("failed to load session").context(err),
@@ -103,6 +134,7 @@ impl SessionStore for SqliteSessionStore {
         use crate::schema::sessions::*;
 
         let session_key = generate_session_key();
+        let session_user_id = Self::session_user_id(&session_state);
         let session_state = match serde_json::to_vec(&session_state) {
             Ok(state) => state,
             Err(err) => {
@@ -118,6 +150,7 @@ impl SessionStore for SqliteSessionStore {
             id: session_key.as_ref().to_string(),
             data: session_state,
             expires: expires_datetime,
+            user_id: session_user_id,
         };
 
         let mut conn = self.conn.lock().await;
@@ -145,6 +178,7 @@ impl SessionStore for SqliteSessionStore {
     ) -> Result<actix_session::storage::SessionKey, actix_session::storage::UpdateError> {
         use crate::schema::sessions::*;
 
+        let session_user_id = Self::session_user_id(&session_state);
         let session_state = match serde_json::to_vec(&session_state) {
             Ok(state) => state,
             Err(err) => {
@@ -159,7 +193,11 @@ impl SessionStore for SqliteSessionStore {
 
         let mut conn = self.conn.lock().await;
         match diesel::update(table.find(session_key.as_ref()))
-            .set((data.eq(session_state), expires.eq(updated_expires_datetime)))
+            .set((
+                data.eq(session_state),
+                expires.eq(updated_expires_datetime),
+                user_id.eq(session_user_id),
+            ))
             .execute(&mut conn)
             .await
         {
@@ -188,7 +226,8 @@ impl SessionStore for SqliteSessionStore {
             .await
         {
             Ok(num_deleted) => {
-                debug_assert!(num_deleted == 1);
+                // logging out everywhere deletes the session before the middleware gets to it
+                debug_assert!(num_deleted <= 1);
             }
             Err(err) => {
                 return Err(anyhow!("failed to delete session from sqlite").context(err));
@@ -225,3 +264,57 @@ impl SessionStore for SqliteSessionStore {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+
+    fn store() -> SqliteSessionStore {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.batch_execute(include_str!(
+            "../migrations/2025-11-23-190921_create_sessions/up.sql"
+        ))
+        .unwrap();
+        conn.batch_execute(include_str!(
+            "../migrations/2025-12-22-193014_add_session_user_id/up.sql"
+        ))
+        .unwrap();
+
+        SqliteSessionStore::new(conn)
+    }
+
+    fn logged_in(user: i32) -> SessionState {
+        HashMap::from([(USER_ID_KEY.to_string(), user.to_string())])
+    }
+
+    #[tokio::test]
+    async fn delete_user_sessions_keeps_other_users() {
+        let store = store();
+        let ttl = actix_web::cookie::time::Duration::hours(1);
+        store.save(logged_in(1), &ttl).await.unwrap();
+        store.save(logged_in(1), &ttl).await.unwrap();
+        let other = store.save(logged_in(2), &ttl).await.unwrap();
+
+        let deleted = store.save(logged_in(1), &ttl).await.unwrap();
+        assert_eq!(store.delete_user_sessions(1).await.unwrap(), 3);
+        assert!(
+            SessionStore::load(&store, &deleted)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(SessionStore::load(&store, &other).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn update_tracks_login() {
+        let store = store();
+        let ttl = actix_web::cookie::time::Duration::hours(1);
+        let session_key = store.save(HashMap::new(), &ttl).await.unwrap();
+
+        store.update(session_key, logged_in(1), &ttl).await.unwrap();
+
+        assert_eq!(store.delete_user_sessions(1).await.unwrap(), 1);
+    }
+}
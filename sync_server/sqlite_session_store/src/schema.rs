@@ -5,5 +5,6 @@ diesel::table! {
         id -> Text,
         data -> Binary,
         expires -> Timestamp,
+        user_id -> Nullable<Integer>,
     }
 }